@@ -4,39 +4,77 @@ use std::future::Future;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::time::Duration;
 
 use actix_identity::Identity;
 use actix_session::Session;
 use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
 use actix_web::{FromRequest, HttpMessage, HttpRequest, web};
 use serde::{Deserialize, Serialize};
 use tokio::fs::read_to_string;
 use tokio::sync::{Mutex, RwLock};
 use tokio::task::spawn_blocking;
 use tracing::instrument;
+use tracing_actix_web::RootSpan;
 
 use crate::context::Context;
 use crate::error::Error;
-use crate::recipes::handle_io_error;
+use crate::i18n::Language;
+use crate::oidc::ExternalUser;
+use crate::recipes::{handle_io_error, read_error};
+use crate::token;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    #[default]
+    Editor,
+    Admin,
+}
 
 #[derive(Debug)]
 pub struct NoPermission(pub Option<String>);
 #[derive(Debug)]
+pub struct ReadPermission(#[allow(unused)] pub String);
+#[derive(Debug)]
 pub struct WritePermission(#[allow(unused)] pub String);
+#[derive(Debug)]
+pub struct AdminPermission(#[allow(unused)] pub String);
 
 trait PermissionCheck: Sized {
-    fn from_user(user: Option<String>) -> Option<Self>;
+    /// Pages open to anonymous visitors treat an invalid bearer token like none.
+    const ANONYMOUS: bool = false;
+
+    fn from_user(user: Option<(String, Role)>) -> Option<Self>;
 }
 
 impl PermissionCheck for NoPermission {
-    fn from_user(user: Option<String>) -> Option<Self> {
-        Some(Self(user))
+    const ANONYMOUS: bool = true;
+
+    fn from_user(user: Option<(String, Role)>) -> Option<Self> {
+        Some(Self(user.map(|(user, _)| user)))
+    }
+}
+
+impl PermissionCheck for ReadPermission {
+    fn from_user(user: Option<(String, Role)>) -> Option<Self> {
+        user.map(|(user, _)| Self(user))
     }
 }
 
 impl PermissionCheck for WritePermission {
-    fn from_user(user: Option<String>) -> Option<Self> {
-        user.map(Self)
+    fn from_user(user: Option<(String, Role)>) -> Option<Self> {
+        user.filter(|(_, role)| *role >= Role::Editor)
+            .map(|(user, _)| Self(user))
+    }
+}
+
+impl PermissionCheck for AdminPermission {
+    fn from_user(user: Option<(String, Role)>) -> Option<Self> {
+        user.filter(|(_, role)| *role == Role::Admin)
+            .map(|(user, _)| Self(user))
     }
 }
 
@@ -49,7 +87,7 @@ async fn user_from_request(
     identity: Option<Identity>,
     session: &Session,
     context: &Context,
-) -> Option<String> {
+) -> Option<(String, Role)> {
     let identity = identity?;
     let user_id = identity.id().unwrap();
     let token_version = session.get(TOKEN_VERSION_IDENTIFIER).ok().flatten()?;
@@ -58,13 +96,18 @@ async fn user_from_request(
         .users
         .check_authenticated(&user_id, token_version)
         .await
-        .map(|()| user_id)
+        .map(|role| (user_id, role))
         .inspect_err(|_| {
             identity.logout();
         })
         .ok()
 }
 
+pub(crate) fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    header.strip_prefix("Bearer ").map(|t| t.trim().to_owned())
+}
+
 pub fn store_session_info(request: &HttpRequest, user_id: String, token_version: u32) {
     Identity::login(&request.extensions(), user_id).unwrap();
     Session::extract(request)
@@ -89,8 +132,20 @@ where
             .expect("context not set")
             .deref()
             .clone();
+        let bearer = bearer_token(req);
+        let span = RootSpan::extract(req).into_inner().ok();
         Box::pin(async move {
-            let user = user_from_request(identity, &session, &context).await;
+            let user = match bearer {
+                Some(token) => match context.users.authenticate_token(&token).await {
+                    Ok(user) => Some(user),
+                    Err(_) if T::ANONYMOUS => None,
+                    Err(e) => return Err(e.into()),
+                },
+                None => user_from_request(identity, &session, &context).await,
+            };
+            if let (Some(span), Some((login, _))) = (span, &user) {
+                span.record("user_id", login);
+            }
             T::from_user(user)
                 .map(Authenticated)
                 .ok_or(Error::Unauthorized.into())
@@ -103,6 +158,118 @@ struct User {
     password: String,
     locked: bool,
     version: u32,
+    #[serde(default)]
+    role: Role,
+    /// Personal access tokens keyed by their name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    tokens: HashMap<String, ApiToken>,
+    /// Overrides the language requested by the browser.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<Language>,
+    /// Users of the same household share a pantry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    household: Option<String>,
+}
+
+impl User {
+    fn new(password: String, locked: bool, role: Role) -> Self {
+        User {
+            password,
+            locked,
+            version: 0,
+            role,
+            tokens: HashMap::new(),
+            language: None,
+            household: None,
+        }
+    }
+}
+
+fn check_login(login: &str) -> Result<(), Error> {
+    if login.chars().count() < 4 {
+        return Err(Error::UserNameTooShort);
+    }
+    Ok(())
+}
+
+fn check_credentials(login: &str, password: &str) -> Result<(), Error> {
+    check_login(login)?;
+    if password.chars().count() < 8 {
+        return Err(Error::PasswordTooShort);
+    }
+    Ok(())
+}
+
+/// The login of a new external user: the preferred username if it follows the rules for logins,
+/// otherwise one derived from the subject.
+fn external_login(preferred_username: Option<String>, subject: &str) -> String {
+    preferred_username
+        .filter(|login| check_login(login).is_ok())
+        .unwrap_or_else(|| format!("user-{}", &token::hash(subject)[..12]))
+}
+
+#[derive(Serialize, Deserialize)]
+struct ApiToken {
+    hash: String,
+    expires: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ApiTokenInfo {
+    pub name: String,
+    pub expires_in_days: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Invite {
+    role: Role,
+    expires: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Store {
+    users: HashMap<String, User>,
+    /// Pending invites keyed by the hash of their token.
+    #[serde(default)]
+    invites: HashMap<String, Invite>,
+    /// Subjects of external identity providers and the users they log in as.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    subjects: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoreFile {
+    Store(Store),
+    /// The format before invites were introduced: a plain map of users.
+    Legacy(HashMap<String, User>),
+}
+
+impl From<StoreFile> for Store {
+    fn from(file: StoreFile) -> Self {
+        match file {
+            StoreFile::Store(store) => store,
+            StoreFile::Legacy(users) => Store {
+                users,
+                ..Store::default()
+            },
+        }
+    }
+}
+
+impl Store {
+    fn take_invite(&mut self, token: &str) -> Result<Role, Error> {
+        let now = token::now();
+        let hash = token::hash(token);
+        let role = self
+            .invites
+            .get(&hash)
+            .filter(|i| i.expires > now)
+            .map(|i| i.role)
+            .ok_or(Error::InvalidInvite)?;
+        self.invites.remove(&hash);
+        Ok(role)
+    }
 }
 
 struct Write(String);
@@ -110,8 +277,8 @@ struct Write(String);
 struct Io(PathBuf);
 
 impl Io {
-    fn prepare(users: &HashMap<String, User>) -> Write {
-        let content = serde_json::to_string(users).unwrap();
+    fn prepare(store: &Store) -> Write {
+        let content = serde_json::to_string(store).unwrap();
         Write(content)
     }
 
@@ -139,48 +306,126 @@ async fn bcrypt_verify(password: impl AsRef<[u8]> + Send + 'static, password_has
 }
 
 pub struct Users {
-    index: RwLock<HashMap<String, User>>,
+    index: RwLock<Store>,
     io: Mutex<Io>,
+    bcrypt_cost: u32,
 }
 
 impl Users {
-    pub async fn load(path: PathBuf) -> Users {
+    pub async fn load(path: PathBuf, bcrypt_cost: u32) -> std::io::Result<Users> {
         let text = read_to_string(&path)
             .await
-            .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
-        let users = serde_json::from_str(&text).unwrap();
-        Users {
-            index: RwLock::new(users),
+            .map_err(|e| read_error(&path, e))?;
+        let store: StoreFile = serde_json::from_str(&text).map_err(|e| read_error(&path, e))?;
+        Ok(Users {
+            index: RwLock::new(store.into()),
             io: Mutex::new(Io(path)),
-        }
+            bcrypt_cost,
+        })
     }
 }
 
 impl Users {
-    #[instrument(skip(self, password), err)]
-    pub async fn register(&self, login: String, password: String) -> Result<(), Error> {
-        if login.chars().count() < 4 {
-            return Err(Error::UserNameTooShort);
-        }
-        if password.chars().count() < 8 {
-            return Err(Error::PasswordTooShort);
-        }
-        let hash = bcrypt_hash(password, bcrypt::DEFAULT_COST).await;
+    pub async fn count(&self) -> usize {
+        self.index.read().await.users.len()
+    }
+
+    /// Whether the users file is still accessible.
+    pub async fn is_available(&self) -> bool {
+        let path = self.io.lock().await.0.clone();
+        tokio::fs::metadata(path).await.is_ok_and(|m| m.is_file())
+    }
+
+    /// Registers a new user. Users registering with a valid invite are unlocked right away and
+    /// get the role of the invite, everyone else has to be unlocked by an administrator.
+    #[instrument(skip(self, password, invite), err)]
+    pub async fn register(
+        &self,
+        login: String,
+        password: String,
+        invite: Option<String>,
+    ) -> Result<(), Error> {
+        check_credentials(&login, &password)?;
+        let hash = bcrypt_hash(password, self.bcrypt_cost).await;
         let mut io = self.io.lock().await;
-        let mut users = self.index.write().await;
-        match users.entry(login) {
-            Entry::Occupied(_) => Err(Error::AlreadyExists),
-            Entry::Vacant(e) => {
-                e.insert(User {
-                    password: hash,
-                    locked: true,
-                    version: 0,
-                });
-                let write = Io::prepare(&users);
-                drop(users);
-                io.write(&write).await
-            }
+        let mut store = self.index.write().await;
+        if store.users.contains_key(&login) {
+            return Err(Error::AlreadyExists);
         }
+        let (locked, role) = match invite {
+            Some(invite) => (false, store.take_invite(&invite)?),
+            None => (true, Role::default()),
+        };
+        store.users.insert(login, User::new(hash, locked, role));
+        let write = Io::prepare(&store);
+        drop(store);
+        io.write(&write).await
+    }
+
+    /// Adds an unlocked user with `role`, bypassing registration.
+    #[instrument(skip(self, password), err)]
+    pub async fn add(&self, login: String, password: String, role: Role) -> Result<(), Error> {
+        check_credentials(&login, &password)?;
+        let hash = bcrypt_hash(password, self.bcrypt_cost).await;
+        let mut io = self.io.lock().await;
+        let mut store = self.index.write().await;
+        match store.users.entry(login) {
+            Entry::Occupied(_) => return Err(Error::AlreadyExists),
+            Entry::Vacant(e) => e.insert(User::new(hash, false, role)),
+        };
+        let write = Io::prepare(&store);
+        drop(store);
+        io.write(&write).await
+    }
+
+    #[instrument(skip(self), err)]
+    pub async fn unlock(&self, login: &str) -> Result<(), Error> {
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(login).ok_or(Error::NotFound)?;
+            user.locked = false;
+            Io::prepare(&store)
+        };
+        io.write(&write).await
+    }
+
+    /// Sets a new password and logs out all sessions of the user.
+    #[instrument(skip(self, password), err)]
+    pub async fn set_password(&self, login: &str, password: String) -> Result<(), Error> {
+        check_credentials(login, &password)?;
+        let hash = bcrypt_hash(password, self.bcrypt_cost).await;
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(login).ok_or(Error::NotFound)?;
+            user.password = hash;
+            user.version += 1;
+            Io::prepare(&store)
+        };
+        io.write(&write).await
+    }
+
+    /// Creates a single-use invite that registers a user with `role` and returns its token.
+    #[instrument(skip(self), err)]
+    pub async fn create_invite(&self, role: Role, valid_for: Duration) -> Result<String, Error> {
+        let token = token::generate();
+        let now = token::now();
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            store.invites.retain(|_, i| i.expires > now);
+            store.invites.insert(
+                token::hash(&token),
+                Invite {
+                    role,
+                    expires: now + valid_for.as_secs(),
+                },
+            );
+            Io::prepare(&store)
+        };
+        io.write(&write).await?;
+        Ok(token)
     }
 
     #[instrument(skip(self, password, req), err)]
@@ -190,8 +435,9 @@ impl Users {
         password: String,
         req: &HttpRequest,
     ) -> Result<(), Error> {
-        let users = self.index.read().await;
-        let (hash, version) = users
+        let store = self.index.read().await;
+        let (hash, version) = store
+            .users
             .get(&login)
             .filter(|u| !u.locked)
             .map(|u| (u.password.clone(), u.version))
@@ -202,15 +448,67 @@ impl Users {
         Ok(())
     }
 
+    /// Logs in the user an external subject belongs to. Unknown subjects are linked to the user
+    /// that started the login or, with `register_role`, get a new user.
+    #[instrument(skip(self, user, req), fields(subject = user.subject), err)]
+    pub async fn login_external(
+        &self,
+        user: ExternalUser,
+        register_role: Option<Role>,
+        req: &HttpRequest,
+    ) -> Result<(), Error> {
+        let mut io = self.io.lock().await;
+        let known = self.index.read().await.subjects.get(&user.subject).cloned();
+        let (login, write) = if let Some(login) = known {
+            (login, None)
+        } else {
+            let (login, new_user) = if let Some(login) = user.link {
+                if !self.index.read().await.users.contains_key(&login) {
+                    return Err(Error::NotFound);
+                }
+                (login, None)
+            } else {
+                let role = register_role.ok_or(Error::Unauthorized)?;
+                let login = external_login(user.preferred_username, &user.subject);
+                if self.index.read().await.users.contains_key(&login) {
+                    return Err(Error::AlreadyExists);
+                }
+                // Every change holds the file lock, so the store stays the same while hashing.
+                let password = bcrypt_hash(token::generate(), self.bcrypt_cost).await;
+                (login, Some(User::new(password, false, role)))
+            };
+            let mut store = self.index.write().await;
+            if let Some(new_user) = new_user {
+                store.users.insert(login.clone(), new_user);
+            }
+            store.subjects.insert(user.subject, login.clone());
+            (login, Some(Io::prepare(&store)))
+        };
+        let version = self
+            .index
+            .read()
+            .await
+            .users
+            .get(&login)
+            .filter(|u| !u.locked)
+            .map(|u| u.version)
+            .ok_or(Error::Unauthorized)?;
+        if let Some(write) = write {
+            io.write(&write).await?;
+        }
+        store_session_info(req, login, version);
+        Ok(())
+    }
+
     #[instrument(skip(self, req), err)]
     pub async fn invalidate_sessions(&self, login: String, req: &HttpRequest) -> Result<(), Error> {
         let mut io = self.io.lock().await;
         let (write, version) = {
-            let mut users = self.index.write().await;
-            let user = users.get_mut(&login).ok_or(Error::NotFound)?;
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(&login).ok_or(Error::NotFound)?;
             user.version += 1;
             let version = user.version;
-            (Io::prepare(&users), version)
+            (Io::prepare(&store), version)
         };
 
         io.write(&write).await?;
@@ -219,46 +517,225 @@ impl Users {
     }
 
     #[instrument(level = "debug", skip(self))]
-    pub async fn check_authenticated(&self, login: &str, token_version: u32) -> Result<(), Error> {
-        let users = self.index.read().await;
-        users
+    pub async fn check_authenticated(
+        &self,
+        login: &str,
+        token_version: u32,
+    ) -> Result<Role, Error> {
+        let store = self.index.read().await;
+        store
+            .users
             .get(login)
             .filter(|u| !u.locked && u.version == token_version)
-            .map(|_| ())
+            .map(|u| u.role)
             .ok_or(Error::Unauthorized)
     }
+
+    /// Creates a named personal access token for `login` and returns it. Only its hash is stored.
+    #[instrument(skip(self), err)]
+    pub async fn create_token(
+        &self,
+        login: &str,
+        name: String,
+        valid_for: Option<Duration>,
+    ) -> Result<String, Error> {
+        if name.is_empty() {
+            return Err(Error::EmptyName);
+        }
+        let token = token::generate();
+        let expires = valid_for.map(|d| token::now() + d.as_secs());
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(login).ok_or(Error::NotFound)?;
+            match user.tokens.entry(name) {
+                Entry::Occupied(_) => return Err(Error::AlreadyExists),
+                Entry::Vacant(e) => e.insert(ApiToken {
+                    hash: token::hash(&token),
+                    expires,
+                }),
+            };
+            Io::prepare(&store)
+        };
+        io.write(&write).await?;
+        Ok(token)
+    }
+
+    #[instrument(skip(self), err)]
+    pub async fn revoke_token(&self, login: &str, name: &str) -> Result<(), Error> {
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(login).ok_or(Error::NotFound)?;
+            user.tokens.remove(name).ok_or(Error::NotFound)?;
+            Io::prepare(&store)
+        };
+        io.write(&write).await
+    }
+
+    pub async fn tokens(&self, login: &str) -> Vec<ApiTokenInfo> {
+        let now = token::now();
+        let store = self.index.read().await;
+        let mut tokens: Vec<_> = store
+            .users
+            .get(login)
+            .into_iter()
+            .flat_map(|u| &u.tokens)
+            .map(|(name, t)| ApiTokenInfo {
+                name: name.clone(),
+                expires_in_days: t.expires.map(|e| e.saturating_sub(now) / (24 * 60 * 60)),
+            })
+            .collect();
+        tokens.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        tokens
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub async fn authenticate_token(&self, token: &str) -> Result<(String, Role), Error> {
+        let now = token::now();
+        let hash = token::hash(token);
+        let store = self.index.read().await;
+        store
+            .users
+            .iter()
+            .filter(|(_, u)| !u.locked)
+            .find(|(_, u)| {
+                u.tokens
+                    .values()
+                    .any(|t| t.hash == hash && t.expires.is_none_or(|e| e > now))
+            })
+            .map(|(login, u)| (login.clone(), u.role))
+            .ok_or(Error::Unauthorized)
+    }
+
+    pub async fn role(&self, login: &str) -> Option<Role> {
+        let store = self.index.read().await;
+        store.users.get(login).map(|u| u.role)
+    }
+
+    pub async fn language(&self, login: &str) -> Option<Language> {
+        let store = self.index.read().await;
+        store.users.get(login).and_then(|u| u.language)
+    }
+
+    /// The key of the pantry a user uses: their household's, or their own without one. Logins
+    /// and household names are kept apart, so neither can take over the other's pantry.
+    pub async fn pantry_key(&self, login: &str) -> String {
+        let store = self.index.read().await;
+        match store.users.get(login).and_then(|u| u.household.as_ref()) {
+            Some(household) => format!("household:{household}"),
+            None => format!("user:{login}"),
+        }
+    }
+
+    #[instrument(skip(self), err)]
+    pub async fn set_household(&self, login: &str, household: Option<String>) -> Result<(), Error> {
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(login).ok_or(Error::NotFound)?;
+            user.household = household;
+            Io::prepare(&store)
+        };
+        io.write(&write).await
+    }
+
+    /// Changes the role of a user, which applies to their existing sessions and tokens as well.
+    #[instrument(skip(self), err)]
+    pub async fn set_role(&self, login: &str, role: Role) -> Result<(), Error> {
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(login).ok_or(Error::NotFound)?;
+            user.role = role;
+            Io::prepare(&store)
+        };
+        io.write(&write).await
+    }
+
+    #[instrument(skip(self), err)]
+    pub async fn set_language(&self, login: &str, language: Option<Language>) -> Result<(), Error> {
+        let mut io = self.io.lock().await;
+        let write = {
+            let mut store = self.index.write().await;
+            let user = store.users.get_mut(login).ok_or(Error::NotFound)?;
+            user.language = language;
+            Io::prepare(&store)
+        };
+        io.write(&write).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use actix_web::cookie::Cookie;
-    use actix_web::http::header::SET_COOKIE;
+    use actix_web::http::header::{AUTHORIZATION, SET_COOKIE};
     use actix_web::http::{Method, StatusCode};
     use actix_web::test;
 
-    use crate::routes::tests::app;
-    use crate::setup_tracing;
+    use tempfile::TempDir;
+
+    use super::{Role, Users, external_login};
+    use crate::error::Error;
+    use crate::routes::tests::{app, app_with, csrf_token_of, make_app_data_with_users};
+    use crate::settings::LogConfig;
+    use crate::telemetry;
 
     #[actix_web::test]
     async fn test_auth_flow() {
-        setup_tracing();
+        let _telemetry = telemetry::setup(&LogConfig::default());
         let app = test::init_service(app().await).await;
 
         let req = test::TestRequest::with_uri("/login")
             .method(Method::GET)
             .to_request();
-        let resp = test::call_and_read_body(&app, req).await;
-        let body = std::str::from_utf8(&resp).unwrap();
+        let resp = test::call_service(&app, req).await;
+        let cookie = resp
+            .headers()
+            .get(SET_COOKIE)
+            .expect("expecting set cookie header");
+        let cookie = Cookie::parse_encoded(cookie.to_str().unwrap().to_owned()).unwrap();
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
         assert!(!body.contains("admin"));
+        let csrf_token = csrf_token_of(body);
+
+        let req = test::TestRequest::with_uri("/login")
+            .method(Method::POST)
+            .cookie(cookie.clone())
+            .set_form(serde_json::json!({
+                "user": "admin",
+                "password": "adminadmin"
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
+        // An invalid bearer token does not skip the check.
         let req = test::TestRequest::with_uri("/login")
             .method(Method::POST)
+            .cookie(cookie.clone())
+            .insert_header((AUTHORIZATION, "Bearer invalid"))
             .set_form(serde_json::json!({
                 "user": "admin",
                 "password": "adminadmin"
             }))
             .to_request();
         let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::with_uri("/login")
+            .method(Method::POST)
+            .cookie(cookie)
+            .set_form(serde_json::json!({
+                "user": "admin",
+                "password": "adminadmin",
+                "csrf_token": csrf_token,
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::SEE_OTHER);
 
         let cookie = resp
@@ -276,4 +753,151 @@ mod tests {
         let body = std::str::from_utf8(&body).unwrap();
         assert!(body.contains("admin"));
     }
+
+    #[tokio::test]
+    async fn test_invite() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("users.json");
+        // legacy format without invites
+        std::fs::write(&path, "{}").unwrap();
+        let users = Users::load(path.clone(), 4).await.unwrap();
+
+        let token = users
+            .create_invite(Role::Reader, Duration::from_hours(1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            users
+                .register("user".into(), "password".into(), Some("wrong".into()))
+                .await,
+            Err(Error::InvalidInvite)
+        ));
+        users
+            .register("user".into(), "password".into(), Some(token.clone()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            users.check_authenticated("user", 0).await,
+            Ok(Role::Reader)
+        ));
+        assert!(matches!(
+            users
+                .register("user2".into(), "password".into(), Some(token))
+                .await,
+            Err(Error::InvalidInvite)
+        ));
+
+        let users = Users::load(path.clone(), 4).await.unwrap();
+        assert_eq!(users.role("user").await, Some(Role::Reader));
+
+        users.set_role("user", Role::Editor).await.unwrap();
+        assert!(matches!(
+            users.check_authenticated("user", 0).await,
+            Ok(Role::Editor)
+        ));
+        assert!(matches!(
+            users.set_role("nobody", Role::Editor).await,
+            Err(Error::NotFound)
+        ));
+        let users = Users::load(path, 4).await.unwrap();
+        assert_eq!(users.role("user").await, Some(Role::Editor));
+    }
+
+    #[actix_web::test]
+    async fn test_external_login() {
+        assert_eq!(
+            external_login(Some("external".into()), "issuer subject"),
+            "external"
+        );
+        let derived = external_login(None, "issuer subject");
+        assert!(derived.starts_with("user-"));
+        assert_eq!(
+            external_login(Some("ext".into()), "issuer subject"),
+            derived
+        );
+        assert_ne!(external_login(None, "issuer other"), derived);
+    }
+
+    #[tokio::test]
+    async fn test_pantry_key() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("users.json");
+        std::fs::write(&path, "{}").unwrap();
+        let users = Users::load(path, 4).await.unwrap();
+        for login in ["alice", "robert", "carol"] {
+            users
+                .add(login.into(), "password".into(), Role::Reader)
+                .await
+                .unwrap();
+        }
+        users
+            .set_household("alice", Some("robert".into()))
+            .await
+            .unwrap();
+        assert_ne!(
+            users.pantry_key("alice").await,
+            users.pantry_key("robert").await
+        );
+        users
+            .set_household("carol", Some("robert".into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            users.pantry_key("alice").await,
+            users.pantry_key("carol").await
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_api_token() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("users.json");
+        std::fs::copy("tests/users.json", &path).unwrap();
+        let users = Users::load(path, 4).await.unwrap();
+        let token = users
+            .create_token("admin", "script".into(), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            users.create_token("admin", "script".into(), None).await,
+            Err(Error::AlreadyExists)
+        ));
+        let app = test::init_service(app_with(make_app_data_with_users(users).await)).await;
+
+        let req = test::TestRequest::with_uri("/login")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&resp).unwrap();
+        assert!(body.contains("admin"));
+
+        // Public pages are shown to invalid tokens like to anonymous visitors.
+        let req = test::TestRequest::with_uri("/login")
+            .insert_header((AUTHORIZATION, "Bearer invalid"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = test::read_body(resp).await;
+        assert!(!std::str::from_utf8(&body).unwrap().contains("admin"));
+
+        let req = test::TestRequest::with_uri("/pantry")
+            .insert_header((AUTHORIZATION, "Bearer invalid"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::with_uri("/tokens/revoke")
+            .method(Method::POST)
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .set_form(serde_json::json!({ "name": "script" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+
+        let req = test::TestRequest::with_uri("/pantry")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 }
@@ -0,0 +1,305 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLockReadGuard;
+use tracing::instrument;
+
+use crate::error::Error;
+use crate::store::JsonStore;
+
+/// What is known about an ingredient independent of the recipes. The key of the entry is the
+/// canonical name.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CatalogueEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plural: Option<String>,
+    /// Other names that are replaced by the canonical one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub synonyms: Vec<String>,
+    /// In g/ml, to convert between mass and volume.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density: Option<f64>,
+    /// Usually at home, like salt or water, so it can be left out when looking for recipes.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub staple: bool,
+}
+
+/// Whether two names are the same, ignoring case also beyond ASCII, like `Öl` and `öl`.
+fn same_name(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+impl CatalogueEntry {
+    /// All names besides the canonical one.
+    fn forms(&self) -> impl Iterator<Item = &str> {
+        self.plural.iter().chain(&self.synonyms).map(String::as_str)
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase", from = "CatalogueFile")]
+pub struct CatalogueData {
+    ingredients: BTreeMap<String, CatalogueEntry>,
+    /// All names in lowercase, with their canonical name and whether they are the plural.
+    #[serde(skip)]
+    index: HashMap<String, (String, bool)>,
+}
+
+/// The stored fields of [`CatalogueData`], which builds its index from them.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CatalogueFile {
+    ingredients: BTreeMap<String, CatalogueEntry>,
+}
+
+impl From<CatalogueFile> for CatalogueData {
+    fn from(file: CatalogueFile) -> CatalogueData {
+        CatalogueData::new(file.ingredients)
+    }
+}
+
+impl CatalogueData {
+    pub fn new(ingredients: BTreeMap<String, CatalogueEntry>) -> CatalogueData {
+        let mut data = CatalogueData {
+            ingredients,
+            index: HashMap::new(),
+        };
+        data.reindex();
+        data
+    }
+
+    /// Entries keyed by their canonical name.
+    pub fn ingredients(&self) -> &BTreeMap<String, CatalogueEntry> {
+        &self.ingredients
+    }
+
+    /// Rebuilds the index after the entries changed. The first entry with a name wins, and an
+    /// entry's plural wins over its other names.
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (canonical, entry) in &self.ingredients {
+            let names = entry
+                .plural
+                .iter()
+                .map(|p| (p, true))
+                .chain(std::iter::once((canonical, false)))
+                .chain(entry.synonyms.iter().map(|s| (s, false)));
+            for (name, plural) in names {
+                self.index
+                    .entry(name.trim().to_lowercase())
+                    .or_insert_with(|| (canonical.clone(), plural));
+            }
+        }
+    }
+
+    /// Finds an entry by its name, plural or a synonym, ignoring case and surrounding whitespace.
+    /// Returns the canonical name, the entry and whether the plural matched.
+    fn find(&self, name: &str) -> Option<(&str, &CatalogueEntry, bool)> {
+        let (canonical, plural) = self.index.get(&name.trim().to_lowercase())?;
+        let (canonical, entry) = self.ingredients.get_key_value(canonical)?;
+        Some((canonical.as_str(), entry, *plural))
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&CatalogueEntry> {
+        self.find(name).map(|(_, e, _)| e)
+    }
+
+    /// The key ingredients are grouped by: the canonical name if the ingredient is known, always
+    /// in lowercase.
+    pub fn canonical(&self, name: &str) -> String {
+        self.find(name)
+            .map_or(name, |(canonical, _, _)| canonical)
+            .trim()
+            .to_lowercase()
+    }
+
+    /// The spelling of the catalogue, keeping the plural. `None` for unknown ingredients.
+    pub fn normalize(&self, name: &str) -> Option<String> {
+        let (canonical, entry, plural) = self.find(name)?;
+        match (plural, &entry.plural) {
+            (true, Some(plural)) => Some(plural.clone()),
+            _ => Some(canonical.to_owned()),
+        }
+    }
+
+    /// Canonical names and plurals, for autocompletion.
+    pub fn names(&self) -> Vec<&str> {
+        self.ingredients
+            .iter()
+            .flat_map(|(name, e)| std::iter::once(name.as_str()).chain(e.plural.as_deref()))
+            .collect()
+    }
+
+    /// The name and all other names of its entry, e.g. to find mentions of an ingredient in a
+    /// text.
+    pub fn spellings(&self, name: &str) -> Vec<String> {
+        let mut spellings: Vec<String> = Vec::new();
+        let others = self
+            .find(name)
+            .map(|(canonical, entry, _)| std::iter::once(canonical).chain(entry.forms()));
+        for spelling in std::iter::once(name).chain(others.into_iter().flatten()) {
+            let spelling = spelling.trim();
+            if !spelling.is_empty() && !spellings.iter().any(|s| same_name(s, spelling)) {
+                spellings.push(spelling.to_owned());
+            }
+        }
+        spellings
+    }
+
+    pub fn density(&self, name: &str) -> Option<f64> {
+        self.entry(name)?.density
+    }
+
+    pub fn is_staple(&self, name: &str) -> bool {
+        self.entry(name).is_some_and(|e| e.staple)
+    }
+}
+
+/// The ingredient catalogue, maintained by admins.
+pub struct Catalogue {
+    store: JsonStore<CatalogueData>,
+}
+
+impl Catalogue {
+    /// Starts with an empty catalogue if the file does not exist yet.
+    pub async fn load(path: PathBuf) -> std::io::Result<Catalogue> {
+        Ok(Catalogue {
+            store: JsonStore::load(path).await?,
+        })
+    }
+
+    pub async fn data(&self) -> RwLockReadGuard<'_, CatalogueData> {
+        self.store.read().await
+    }
+
+    /// Adds or replaces the entry with the canonical name, ignoring its case.
+    #[instrument(skip(self))]
+    pub async fn save(&self, name: &str, mut entry: CatalogueEntry) -> Result<(), Error> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(Error::EmptyName);
+        }
+        if entry.density.is_some_and(|d| !d.is_finite() || d <= 0.0) {
+            return Err(Error::InvalidDensity);
+        }
+        entry.plural = entry
+            .plural
+            .map(|p| p.trim().to_owned())
+            .filter(|p| !p.is_empty());
+        let mut synonyms: Vec<String> = Vec::new();
+        for synonym in entry.synonyms.iter().map(|s| s.trim()) {
+            if !synonym.is_empty() && !synonyms.iter().any(|s| same_name(s, synonym)) {
+                synonyms.push(synonym.to_owned());
+            }
+        }
+        entry.synonyms = synonyms;
+
+        self.store
+            .update(|data| {
+                let key = data
+                    .find(name)
+                    .filter(|(canonical, _, _)| same_name(canonical, name))
+                    .map(|(canonical, _, _)| canonical.to_owned());
+                let taken = std::iter::once(name)
+                    .chain(entry.forms())
+                    .filter_map(|form| data.find(form))
+                    .any(|(canonical, _, _)| Some(canonical) != key.as_deref());
+                if taken {
+                    return Err(Error::AlreadyExists);
+                }
+                if let Some(key) = key {
+                    data.ingredients.remove(&key);
+                }
+                data.ingredients.insert(name.to_owned(), entry);
+                data.reindex();
+                Ok(())
+            })
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn remove(&self, name: &str) -> Result<(), Error> {
+        self.store
+            .update(|data| {
+                data.ingredients.remove(name).ok_or(Error::NotFound)?;
+                data.reindex();
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::{Catalogue, CatalogueEntry};
+    use crate::error::Error;
+
+    #[tokio::test]
+    async fn test_save() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("catalogue.json");
+        let catalogue = Catalogue::load(path.clone()).await.unwrap();
+
+        let entry = |plural: &str, synonyms: &[&str], density| CatalogueEntry {
+            plural: Some(plural.into()),
+            synonyms: synonyms.iter().map(|&s| s.into()).collect(),
+            density,
+            staple: false,
+        };
+        catalogue
+            .save("zwiebel", entry("Zwiebeln", &[" Gemüsezwiebel", ""], None))
+            .await
+            .unwrap();
+        catalogue
+            .save("Zwiebel", entry("Zwiebeln", &["Gemüsezwiebel"], Some(0.6)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            catalogue
+                .save("Schalotte", entry("zwiebeln", &[], None))
+                .await,
+            Err(Error::AlreadyExists)
+        ));
+        assert!(matches!(
+            catalogue.save("Mehl", entry("", &[], Some(0.0))).await,
+            Err(Error::InvalidDensity)
+        ));
+        catalogue
+            .save("Öl", entry("", &["Speiseöl", "SPEISEÖL"], None))
+            .await
+            .unwrap();
+        catalogue
+            .save("öl", entry("", &["Speiseöl"], Some(0.9)))
+            .await
+            .unwrap();
+        assert_eq!(
+            catalogue.data().await.normalize("SPEISEÖL").as_deref(),
+            Some("öl")
+        );
+
+        {
+            let catalogue = Catalogue::load(path.clone()).await.unwrap();
+            let data = catalogue.data().await;
+            assert_eq!(data.ingredients().len(), 2);
+            assert_eq!(data.entry("Öl").unwrap().synonyms, ["Speiseöl"]);
+            assert_eq!(data.density("speiseöl"), Some(0.9));
+            assert_eq!(data.density("gemüsezwiebel"), Some(0.6));
+            assert_eq!(data.normalize("zwiebel ").as_deref(), Some("Zwiebel"));
+            assert_eq!(data.normalize("ZWIEBELN").as_deref(), Some("Zwiebeln"));
+            assert_eq!(data.normalize("Gemüsezwiebel").as_deref(), Some("Zwiebel"));
+            assert_eq!(data.normalize("Knoblauch"), None);
+            assert_eq!(data.canonical("Zwiebeln"), "zwiebel");
+            assert_eq!(data.canonical("Knoblauch "), "knoblauch");
+            assert_eq!(data.names(), ["Zwiebel", "Zwiebeln", "öl"]);
+        }
+
+        catalogue.remove("Zwiebel").await.unwrap();
+        assert_eq!(catalogue.data().await.normalize("Zwiebeln"), None);
+        catalogue.remove("öl").await.unwrap();
+        assert!(catalogue.data().await.ingredients().is_empty());
+    }
+}
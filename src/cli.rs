@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use clap::Subcommand;
+
+use crate::auth::{Role, Users};
+use crate::catalogue::Catalogue;
+use crate::error::Error;
+use crate::id::to_id_string;
+use crate::nutrition::{Columns, Nutrition, read_csv};
+use crate::recipe::RawRecipe;
+use crate::recipes::{Recipes, read_error};
+use crate::settings::Config;
+
+// The administrative commands work on the files directly, so the server should not be running at
+// the same time, except for the nutrition commands: the server only reads that file and reloads it.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the web server, the default.
+    Serve,
+    /// Manage users.
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Maintain the recipes directory.
+    #[command(subcommand)]
+    Recipe(RecipeCommand),
+    /// Manage the nutrition database. A running server reloads it after changes.
+    #[command(subcommand)]
+    Nutrition(NutritionCommand),
+    /// Write all recipes into a single JSON file.
+    Export { file: PathBuf },
+    /// Add the recipes of a file written by `export`.
+    Import {
+        file: PathBuf,
+        /// Overwrite recipes that already exist.
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Add an unlocked user. The password is read from stdin.
+    Add {
+        login: String,
+        #[arg(long, default_value = "editor", value_parser = parse_role)]
+        role: Role,
+    },
+    /// Unlock a registered user.
+    Unlock { login: String },
+    /// Set the password of a user, read from stdin, and log out all their sessions.
+    Passwd { login: String },
+    /// Change the role of a user: reader, editor or admin.
+    Role {
+        login: String,
+        #[arg(value_parser = parse_role)]
+        role: Role,
+    },
+    /// Let a user share the pantry of a household, or use their own without a household.
+    Household {
+        login: String,
+        household: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RecipeCommand {
+    /// Check that every recipe has a unique, non-empty slug.
+    Validate,
+    /// Rename recipe files named after their slug to permanent ids, keeping the slug as alias.
+    /// The ids are recorded in `<recipes directory>.ids.json`, so an interrupted run can be
+    /// repeated and later runs leave the files alone.
+    ReindexIds,
+}
+
+#[derive(Subcommand)]
+pub enum NutritionCommand {
+    /// Add foods from a CSV file with a header row and values per 100 g.
+    Import {
+        file: PathBuf,
+        #[arg(long, default_value_t = ',')]
+        delimiter: char,
+        #[arg(long, default_value = "name")]
+        name_column: String,
+        #[arg(long, default_value = "kcal")]
+        kcal_column: String,
+        #[arg(long, default_value = "protein")]
+        protein_column: String,
+        #[arg(long, default_value = "fat")]
+        fat_column: String,
+        #[arg(long, default_value = "carbohydrates")]
+        carbohydrates_column: String,
+        /// Weight of one piece in grams, used if the column exists.
+        #[arg(long, default_value = "piece_grams")]
+        piece_grams_column: String,
+    },
+    /// Use a food for an ingredient name in all recipes, or remove the mapping without a food.
+    Map {
+        ingredient: String,
+        food: Option<String>,
+    },
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    serde_json::from_value(serde_json::Value::String(s.into()))
+        .map_err(|_| "expected one of reader, editor, admin".into())
+}
+
+fn failed(e: &Error) -> std::io::Error {
+    std::io::Error::other(format!("Failed: {e}"))
+}
+
+fn read_password() -> std::io::Result<String> {
+    eprint!("Password: ");
+    std::io::stderr().flush()?;
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Runs every command except [`Command::Serve`].
+pub async fn run(config: Config, command: Command) -> std::io::Result<()> {
+    let paths = config.paths;
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::User(command) => {
+            let users = Users::load(paths.users, config.bcrypt_cost).await?;
+            match command {
+                UserCommand::Add { login, role } => {
+                    let password = read_password()?;
+                    users
+                        .add(login, password, role)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                }
+                UserCommand::Unlock { login } => {
+                    users.unlock(&login).await.map_err(|e| failed(&e))?;
+                }
+                UserCommand::Passwd { login } => {
+                    let password = read_password()?;
+                    users
+                        .set_password(&login, password)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                }
+                UserCommand::Role { login, role } => {
+                    users.set_role(&login, role).await.map_err(|e| failed(&e))?;
+                }
+                UserCommand::Household { login, household } => {
+                    users
+                        .set_household(&login, household)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                }
+            }
+        }
+        Command::Recipe(RecipeCommand::Validate) => {
+            let issues = Recipes::check_dir(&paths.recipes).await?;
+            for issue in &issues {
+                println!("{issue}");
+            }
+            if !issues.is_empty() {
+                return Err(std::io::Error::other(format!(
+                    "Found {} issues",
+                    issues.len()
+                )));
+            }
+        }
+        Command::Recipe(RecipeCommand::ReindexIds) => {
+            for (from, to) in Recipes::reindex_dir(&paths.recipes).await? {
+                println!("{} -> {}", from.display(), to.display());
+            }
+        }
+        Command::Nutrition(command) => {
+            let nutrition = Nutrition::load(paths.nutrition).await?;
+            run_nutrition(&nutrition, command).await?;
+        }
+        Command::Export { file } => {
+            let recipes = Recipes::load_dir(&paths.recipes).await?;
+            let recipes = recipes.list().await;
+            let recipes: BTreeMap<_, _> = recipes.iter().collect();
+            let content = serde_json::to_string_pretty(&recipes).unwrap();
+            tokio::fs::write(&file, content).await?;
+            println!("Exported {} recipes", recipes.len());
+        }
+        Command::Import { file, replace } => {
+            let text = tokio::fs::read_to_string(&file)
+                .await
+                .map_err(|e| read_error(&file, e))?;
+            let imported: HashMap<String, RawRecipe> =
+                serde_json::from_str(&text).map_err(|e| read_error(&file, e))?;
+            let recipes = Recipes::load_dir(&paths.recipes).await?;
+            let catalogue = Catalogue::load(paths.catalogue).await?;
+            let catalogue = catalogue.data().await;
+            let mut count = 0;
+            for (id, mut recipe) in imported {
+                recipe.clean(&catalogue);
+                if to_id_string(&recipe.name).is_empty() {
+                    println!("Skipping {:?}, its slug is empty", recipe.name);
+                    continue;
+                }
+                match recipes.create(id.clone(), recipe.clone()).await {
+                    Ok(()) => {}
+                    Err(Error::AlreadyExists) if replace => {
+                        recipes.replace(&id, recipe).await.map_err(|e| failed(&e))?;
+                    }
+                    Err(Error::AlreadyExists) => {
+                        println!("Skipping {id}, it already exists");
+                        continue;
+                    }
+                    Err(e) => return Err(failed(&e)),
+                }
+                count += 1;
+            }
+            println!("Imported {count} recipes");
+        }
+    }
+    Ok(())
+}
+
+async fn run_nutrition(nutrition: &Nutrition, command: NutritionCommand) -> std::io::Result<()> {
+    match command {
+        NutritionCommand::Import {
+            file,
+            delimiter,
+            name_column,
+            kcal_column,
+            protein_column,
+            fat_column,
+            carbohydrates_column,
+            piece_grams_column,
+        } => {
+            let delimiter = u8::try_from(delimiter)
+                .map_err(|_| std::io::Error::other("The delimiter must be an ASCII character"))?;
+            let columns = Columns {
+                name: &name_column,
+                energy_kcal: &kcal_column,
+                protein: &protein_column,
+                fat: &fat_column,
+                carbohydrates: &carbohydrates_column,
+                piece_grams: &piece_grams_column,
+            };
+            let reader = std::fs::File::open(&file).map_err(|e| read_error(&file, e))?;
+            let (foods, skipped) =
+                read_csv(reader, delimiter, &columns).map_err(|e| read_error(&file, e))?;
+            let count = foods.len();
+            nutrition.import(foods).await.map_err(|e| failed(&e))?;
+            println!("Imported {count} foods, skipped {skipped} rows without values");
+        }
+        NutritionCommand::Map { ingredient, food } => {
+            nutrition
+                .map(&ingredient, food)
+                .await
+                .map_err(|e| failed(&e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use tempfile::TempDir;
+
+    use super::{Command, RecipeCommand, run};
+    use crate::recipes::Recipes;
+    use crate::settings::Config;
+
+    fn config(dir: &TempDir, recipes: &Path) -> Config {
+        let path = dir.path().join("Recipes.toml");
+        let content = format!(
+            "address = \"\"\ncookies_key = \"{}\"\n[paths]\nrecipes = {:?}\n",
+            "k".repeat(64),
+            recipes.display().to_string()
+        );
+        std::fs::write(&path, content).unwrap();
+        Config::load(&path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("imported")).unwrap();
+        let file = dir.path().join("export.json");
+
+        run(
+            config(&dir, Path::new("tests/recipes")),
+            Command::Export { file: file.clone() },
+        )
+        .await
+        .unwrap();
+        run(
+            config(&dir, &dir.path().join("imported")),
+            Command::Import {
+                file,
+                replace: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        let recipes = Recipes::load_dir(&dir.path().join("imported"))
+            .await
+            .unwrap();
+        let mut ids: Vec<_> = recipes.list().await.keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, ["test-1", "test-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_reindex_ids() {
+        let dir = TempDir::new().unwrap();
+        let recipes_dir = dir.path().join("recipes");
+        std::fs::create_dir(&recipes_dir).unwrap();
+        let recipe = |name: &str, section_recipe: &str| {
+            format!(
+                r#"{{"Name":"{name}","Description":"","IngredientsSections":[{{"Heading":"",
+                "Recipe":{section_recipe},"Ingredients":[]}}],"Instructions":"","Source":""}}"#
+            )
+        };
+        let write = |id: &str, content: String| {
+            std::fs::write(recipes_dir.join(id).with_extension("json"), content).unwrap();
+        };
+        write("teig", recipe("Mürbeteig", "null"));
+        write("tarte", recipe("Tarte", "\"teig\""));
+        write("cafe00c0ffee", recipe("Cafe00c0ffee", "null"));
+        let reindex = || {
+            run(
+                config(&dir, &recipes_dir),
+                Command::Recipe(RecipeCommand::ReindexIds),
+            )
+        };
+        reindex().await.unwrap();
+
+        let record = dir.path().join("recipes.ids.json");
+        let ids: HashMap<String, String> =
+            serde_json::from_str(&std::fs::read_to_string(&record).unwrap()).unwrap();
+        let recipes = Recipes::load_dir(&recipes_dir).await.unwrap();
+        let mut moved: Vec<_> = recipes.list().await.keys().cloned().collect();
+        moved.sort();
+        let mut expected: Vec<_> = ids.values().cloned().collect();
+        expected.sort();
+        assert_eq!(moved, expected);
+        let (teig, _) = recipes.resolve("teig").await.unwrap();
+        let (tarte, slug) = recipes.resolve("tarte").await.unwrap();
+        assert_eq!(slug, "tarte");
+        assert_eq!(
+            recipes.resolve("cafe00c0ffee").await.unwrap().0,
+            ids["cafe00c0ffee"]
+        );
+        let tarte = recipes.get(&tarte).await.unwrap();
+        assert!(tarte.aliases.is_empty());
+        assert_eq!(tarte.references().collect::<Vec<_>>(), [teig.as_str()]);
+        assert_eq!(recipes.get(&teig).await.unwrap().aliases, ["teig"]);
+
+        // Another run leaves the moved files alone.
+        reindex().await.unwrap();
+        let recipes = Recipes::load_dir(&recipes_dir).await.unwrap();
+        assert_eq!(recipes.list().await.len(), 3);
+        assert!(recipes.list().await.contains_key(&ids["tarte"]));
+
+        // An interrupted run is finished with the recorded ids.
+        for id in ids.values() {
+            std::fs::remove_file(recipes_dir.join(id).with_extension("json")).unwrap();
+        }
+        std::fs::write(&record, r#"{"teig":"000000000001","tarte":"000000000002"}"#).unwrap();
+        write("teig", recipe("Mürbeteig", "null"));
+        write("000000000001", recipe("Mürbeteig", "null"));
+        write("tarte", recipe("Tarte", "\"teig\""));
+        reindex().await.unwrap();
+        let recipes = Recipes::load_dir(&recipes_dir).await.unwrap();
+        let mut ids: Vec<_> = recipes.list().await.keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, ["000000000001", "000000000002"]);
+        let tarte = recipes.get("000000000002").await.unwrap();
+        assert_eq!(tarte.references().collect::<Vec<_>>(), ["000000000001"]);
+    }
+}
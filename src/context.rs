@@ -1,11 +1,22 @@
 use tokio::sync::RwLock;
 
 use crate::auth::Users;
+use crate::catalogue::Catalogue;
+use crate::nutrition::Nutrition;
+use crate::oidc::Oidc;
+use crate::pantry::Pantry;
 use crate::recipes::Recipes;
 use crate::templates::Templates;
 
 pub struct Context {
     pub templates: RwLock<Templates>,
+    /// Why the last reload of the templates failed.
+    pub template_error: RwLock<Option<String>>,
     pub recipes: Recipes,
     pub users: Users,
+    pub nutrition: Nutrition,
+    pub catalogue: Catalogue,
+    pub pantry: Pantry,
+    pub open_registration: bool,
+    pub oidc: Option<Oidc>,
 }
@@ -0,0 +1,95 @@
+use std::future::{Ready, ready};
+
+use actix_session::{Session, SessionExt};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::web::{Bytes, Data};
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::auth::bearer_token;
+use crate::context::Context;
+use crate::error::Error;
+use crate::token;
+
+const CSRF_TOKEN_IDENTIFIER: &str = "csrf_token";
+const CSRF_HEADER: &str = "x-csrf-token";
+
+fn session_token(session: &Session) -> String {
+    if let Ok(Some(token)) = session.get(CSRF_TOKEN_IDENTIFIER) {
+        return token;
+    }
+    let token = token::generate();
+    session.insert(CSRF_TOKEN_IDENTIFIER, &token).unwrap();
+    token
+}
+
+/// The CSRF token of the current session, to be embedded into forms and scripts.
+pub struct CsrfToken(pub String);
+
+impl CsrfToken {
+    /// Like the extractor, for pages that only need the token sometimes. Creating it starts a
+    /// session.
+    pub fn of(req: &HttpRequest) -> Self {
+        CsrfToken(session_token(&req.get_session()))
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(CsrfToken::of(req)))
+    }
+}
+
+async fn has_valid_bearer_token(req: &ServiceRequest) -> bool {
+    let Some(token) = bearer_token(req.request()) else {
+        return false;
+    };
+    let Some(context) = req.app_data::<Data<Context>>() else {
+        return false;
+    };
+    context.users.authenticate_token(&token).await.is_ok()
+}
+
+#[derive(Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+/// Rejects POST requests that do not carry the CSRF token of their session, either in the
+/// `X-CSRF-Token` header or as `csrf_token` form field. Requests authenticated by a valid bearer
+/// token do not use cookies and are exempt.
+pub(crate) async fn verify(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if req.method() == Method::POST && !has_valid_bearer_token(&req).await {
+        let expected: Option<String> = req.get_session().get(CSRF_TOKEN_IDENTIFIER).ok().flatten();
+        let provided = match req.headers().get(CSRF_HEADER) {
+            Some(header) => header.to_str().ok().map(str::to_owned),
+            None if req.content_type() == "application/x-www-form-urlencoded" => {
+                let body = req.extract::<Bytes>().await?;
+                let form: Option<CsrfForm> = serde_urlencoded::from_bytes(&body).ok();
+                req.set_payload(Payload::from(body));
+                form.and_then(|f| f.csrf_token)
+            }
+            None => None,
+        };
+        // Hashes are compared, so the time taken does not tell how much of the token matched.
+        let valid = expected
+            .zip(provided)
+            .is_some_and(|(e, p)| token::hash(&e) == token::hash(&p));
+        if !valid {
+            let response = HttpResponse::from_error(Error::InvalidCsrfToken);
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
@@ -0,0 +1,116 @@
+use std::ops::Range;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Amounts followed by a unit of time, in German or English, e.g. `20 Minuten`, `1-2 Std.`,
+/// `eine halbe Stunde` or `10 mins`.
+static DURATION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?xi)
+        (?:
+            \b(?P<number>\d+(?:[.,]\d+)?)(?:\s*(?:-|–|bis|to)\s*\d+(?:[.,]\d+)?)?
+            | (?P<half>\b(?:eine\s+)?halbe|½)
+            | \b(?P<one_and_half>anderthalb|eineinhalb)
+            | \b(?P<one>eine[rn]?|an?|one)
+        )
+        \s*
+        (?:
+            (?P<hours>(?:stunden?|hours?|hrs?|h)\b|std\b\.?)
+            | (?P<minutes>(?:minuten?|minutes?|mins)\b|min\b\.?)
+            | (?P<seconds>(?:sekunden?|seconds?|secs?)\b|sek\b\.?)
+        )",
+    )
+    .unwrap()
+});
+
+/// Text between two durations that makes them one, like in `1 Stunde und 20 Minuten`.
+static JOINER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^\s*(?:und|and)?\s*$").unwrap());
+
+/// The durations mentioned in a text, with their position and length in seconds. The lower
+/// bound is used for ranges like `20-25 Minuten`.
+pub fn find_durations(text: &str) -> Vec<(Range<usize>, u32)> {
+    let mut durations: Vec<(Range<usize>, u32, u32)> = Vec::new();
+    for captures in DURATION.captures_iter(text) {
+        let amount = if let Some(number) = captures.name("number") {
+            number.as_str().replace(',', ".").parse().unwrap_or(0.0)
+        } else if captures.name("half").is_some() {
+            0.5
+        } else if captures.name("one_and_half").is_some() {
+            1.5
+        } else {
+            1.0
+        };
+        let unit = if captures.name("hours").is_some() {
+            3600
+        } else if captures.name("minutes").is_some() {
+            60
+        } else {
+            1
+        };
+        // Rounded to whole seconds, durations of a few days at most.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let seconds = (amount * f64::from(unit)).round().min(f64::from(u32::MAX)) as u32;
+        let range = captures.get(0).unwrap().range();
+        match durations.last_mut() {
+            Some((previous, total, previous_unit))
+                if *previous_unit > unit && JOINER.is_match(&text[previous.end..range.start]) =>
+            {
+                previous.end = range.end;
+                *total = total.saturating_add(seconds);
+                *previous_unit = unit;
+            }
+            _ => durations.push((range, seconds, unit)),
+        }
+    }
+    durations
+        .into_iter()
+        .filter(|(_, seconds, _)| *seconds > 0)
+        .map(|(range, seconds, _)| (range, seconds))
+        .collect()
+}
+
+/// Formats minutes like the edit form accepts them, e.g. `1 h 30 min`.
+pub fn format_duration(minutes: u32) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{m} min"),
+        (h, 0) => format!("{h} h"),
+        (h, m) => format!("{h} h {m} min"),
+    }
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(0), "0 min");
+    assert_eq!(format_duration(45), "45 min");
+    assert_eq!(format_duration(120), "2 h");
+    assert_eq!(format_duration(95), "1 h 35 min");
+}
+
+#[test]
+fn test_find_durations() {
+    fn found(text: &str) -> Vec<(&str, u32)> {
+        find_durations(text)
+            .into_iter()
+            .map(|(range, seconds)| (&text[range], seconds))
+            .collect()
+    }
+    assert_eq!(
+        found("Im Ofen 20-25 Minuten backen, dann 1 Std. 30 Min. ruhen lassen."),
+        [("20-25 Minuten", 1200), ("1 Std. 30 Min.", 5400)]
+    );
+    assert_eq!(
+        found("Eine halbe Stunde ziehen lassen und 1,5 h kochen."),
+        [("Eine halbe Stunde", 1800), ("1,5 h", 5400)]
+    );
+    assert_eq!(
+        found("Simmer for an hour and 10 mins, stir for 30 seconds."),
+        [("an hour and 10 mins", 4200), ("30 seconds", 30)]
+    );
+    assert_eq!(found("½ Stunde"), [("½ Stunde", 1800)]);
+    assert_eq!(found("2 Eier und 200 g Mehl verrühren, 0 min"), []);
+    assert_eq!(
+        found("99999999999 Stunden 1 Minute"),
+        [("99999999999 Stunden 1 Minute", u32::MAX)]
+    );
+}
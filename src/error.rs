@@ -1,17 +1,35 @@
 use std::fmt::{Display, Formatter};
 
-use actix_web::ResponseError;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::http::StatusCode;
+use actix_web::http::header::{ACCEPT, CONTENT_TYPE, ContentType, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpResponse, ResponseError};
+use serde_json::json;
+use tera::context;
+
+use crate::context::Context;
+use crate::i18n::Language;
+use crate::recipe::FieldError;
 
 #[derive(Debug)]
 pub enum Error {
     NotFound,
     AlreadyExists,
     Internal,
-    EmptyId,
+    EmptyName,
     Unauthorized,
     UserNameTooShort,
     PasswordTooShort,
+    InvalidInvite,
+    RegistrationClosed,
+    InvalidCsrfToken,
+    InvalidRecipe(Vec<FieldError>),
+    InvalidDensity,
+    InvalidAmount,
+    InvalidDays,
 }
 
 impl Display for Error {
@@ -20,10 +38,17 @@ impl Display for Error {
             Error::AlreadyExists => "already-exists",
             Error::NotFound => "not-found",
             Error::Internal => "internal-error",
-            Error::EmptyId => "empty-id",
+            Error::InvalidRecipe(_) => "invalid-recipe",
+            Error::EmptyName => "empty-name",
             Error::Unauthorized => "unauthorized",
             Error::UserNameTooShort => "user-name-too-short",
             Error::PasswordTooShort => "password-too-short",
+            Error::InvalidInvite => "invalid-invite",
+            Error::RegistrationClosed => "registration-closed",
+            Error::InvalidCsrfToken => "invalid-csrf-token",
+            Error::InvalidDensity => "invalid-density",
+            Error::InvalidAmount => "invalid-amount",
+            Error::InvalidDays => "invalid-days",
         };
         write!(f, "{code}")
     }
@@ -35,10 +60,80 @@ impl ResponseError for Error {
             Error::NotFound => StatusCode::NOT_FOUND,
             Error::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Unauthorized => StatusCode::UNAUTHORIZED,
-            Error::EmptyId
+            Error::RegistrationClosed | Error::InvalidCsrfToken => StatusCode::FORBIDDEN,
+            Error::EmptyName
             | Error::AlreadyExists
             | Error::UserNameTooShort
-            | Error::PasswordTooShort => StatusCode::BAD_REQUEST,
+            | Error::PasswordTooShort
+            | Error::InvalidInvite
+            | Error::InvalidRecipe(_)
+            | Error::InvalidDensity
+            | Error::InvalidAmount
+            | Error::InvalidDays => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            Error::InvalidRecipe(fields) => response.json(json!({
+                "code": self.to_string(),
+                "fields": fields,
+            })),
+            _ => response
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
         }
     }
 }
+
+/// Replaces error responses with a rendered error page for browsers. Other clients, like the
+/// scripts of the edit page, keep getting the plain error code.
+pub(crate) async fn error_page(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody, String>>, actix_web::Error> {
+    let wants_html = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.contains("text/html"));
+    let response = next.call(req).await?;
+    let status = response.status();
+    if !wants_html || !(status.is_client_error() || status.is_server_error()) {
+        return Ok(response.map_into_left_body());
+    }
+    let Some(ctx) = response.request().app_data::<Data<Context>>().cloned() else {
+        return Ok(response.map_into_left_body());
+    };
+
+    let code = response
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<Error>())
+        .map(ToString::to_string);
+    let language = Language::extract(response.request())
+        .await
+        .unwrap_or_default();
+    let context = context!(
+        base_url => "",
+        status => &status.as_u16(),
+        code => &code,
+    );
+    let Ok(rendered) = ctx
+        .templates
+        .read()
+        .await
+        .render("error.html", language, &context)
+    else {
+        return Ok(response.map_into_left_body());
+    };
+
+    let (req, res) = response.into_parts();
+    let mut res = res.set_body(rendered);
+    res.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    Ok(ServiceResponse::new(req, res).map_into_right_body())
+}
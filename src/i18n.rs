@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::LazyLock;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::{AcceptLanguage, Header, Preference};
+use actix_web::{FromRequest, HttpRequest, web};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{Authenticated, NoPermission};
+use crate::context::Context;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    De,
+    En,
+}
+
+impl Language {
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::De => "de",
+            Language::En => "en",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Language> {
+        match code.to_ascii_lowercase().as_str() {
+            "de" => Some(Language::De),
+            "en" => Some(Language::En),
+            _ => None,
+        }
+    }
+
+    /// The best supported language of an `Accept-Language` header.
+    fn accepted(req: &HttpRequest) -> Option<Language> {
+        AcceptLanguage::parse(req)
+            .ok()?
+            .ranked()
+            .into_iter()
+            .find_map(|preference| match preference {
+                Preference::Specific(tag) => Language::from_code(tag.primary_language()),
+                Preference::Any => None,
+            })
+    }
+}
+
+type Catalog = HashMap<String, String>;
+
+static CATALOGS: LazyLock<HashMap<Language, Catalog>> = LazyLock::new(|| {
+    let parse = |text| serde_json::from_str::<Catalog>(text).expect("invalid message catalog");
+    HashMap::from([
+        (Language::De, parse(include_str!("../locales/de.json"))),
+        (Language::En, parse(include_str!("../locales/en.json"))),
+    ])
+});
+
+/// Looks up a message, falling back to German and then to the key itself.
+pub fn translate(language: Language, key: &str) -> &str {
+    CATALOGS[&language]
+        .get(key)
+        .or_else(|| CATALOGS[&Language::default()].get(key))
+        .map_or(key, String::as_str)
+}
+
+/// All messages whose key starts with `prefix`, keyed by the rest of the key.
+pub fn messages(language: Language, prefix: &str) -> HashMap<&'static str, &'static str> {
+    CATALOGS[&language]
+        .iter()
+        .filter_map(|(k, v)| Some((k.strip_prefix(prefix)?, v.as_str())))
+        .collect()
+}
+
+/// The language of the user's preference, or else the one requested by the browser.
+impl FromRequest for Language {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Language, actix_web::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user = Authenticated::<NoPermission>::from_request(req, payload);
+        let accepted = Language::accepted(req);
+        let context = req
+            .app_data::<web::Data<Context>>()
+            .expect("context not set")
+            .deref()
+            .clone();
+        Box::pin(async move {
+            let preferred = match user.await {
+                Ok(Authenticated(NoPermission(Some(login)))) => {
+                    context.users.language(&login).await
+                }
+                _ => None,
+            };
+            Ok(preferred.or(accepted).unwrap_or_default())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::ACCEPT_LANGUAGE;
+    use actix_web::test::TestRequest;
+
+    use super::{CATALOGS, Language, translate};
+
+    #[test]
+    fn test_catalogs_complete() {
+        let de = &CATALOGS[&Language::De];
+        let en = &CATALOGS[&Language::En];
+        let mut missing: Vec<_> = de.keys().filter(|k| !en.contains_key(*k)).collect();
+        missing.extend(en.keys().filter(|k| !de.contains_key(*k)));
+        assert!(missing.is_empty(), "{missing:?}");
+        assert_eq!(translate(Language::En, "recipe.ingredients"), "Ingredients");
+        assert_eq!(translate(Language::En, "unknown.key"), "unknown.key");
+    }
+
+    #[test]
+    fn test_accepted() {
+        let accepted = |header| {
+            let req = TestRequest::default()
+                .insert_header((ACCEPT_LANGUAGE, header))
+                .to_http_request();
+            Language::accepted(&req)
+        };
+        assert_eq!(accepted("en-US,en;q=0.9,de;q=0.8"), Some(Language::En));
+        assert_eq!(accepted("fr;q=0.9,de-AT;q=0.5"), Some(Language::De));
+        assert_eq!(accepted("fr"), None);
+    }
+}
@@ -5,36 +5,194 @@ fn is_not_ok(r: char) -> bool {
         || !(r.is_alphabetic() || r.is_numeric() || r.is_whitespace() || r == '-')
 }
 
-enum Lower {
-    One(char),
-    Two(char, char),
-}
+/// German umlauts and sharp s.
+const GERMAN: Table = &[('ä', "ae"), ('ö', "oe"), ('ü', "ue"), ('ß', "ss")];
+
+/// Other Latin letters without a decomposition.
+const LATIN: Table = &[
+    ('æ', "ae"),
+    ('œ', "oe"),
+    ('ø', "o"),
+    ('å', "aa"),
+    ('ð', "d"),
+    ('đ', "d"),
+    ('þ', "th"),
+    ('ł', "l"),
+    ('ı', "i"),
+    ('ħ', "h"),
+];
+
+type Table = &'static [(char, &'static str)];
+
+/// Russian, also used for other Cyrillic names and letters the other tables lack.
+const RUSSIAN: Table = &[
+    ('а', "a"),
+    ('б', "b"),
+    ('в', "v"),
+    ('г', "g"),
+    ('ґ', "g"),
+    ('д', "d"),
+    ('е', "e"),
+    ('ё', "e"),
+    ('э', "e"),
+    ('є', "ye"),
+    ('ж', "zh"),
+    ('з', "z"),
+    ('и', "i"),
+    ('і', "i"),
+    ('ї', "yi"),
+    ('й', "y"),
+    ('ы', "y"),
+    ('ј', "j"),
+    ('к', "k"),
+    ('л', "l"),
+    ('љ', "lj"),
+    ('м', "m"),
+    ('н', "n"),
+    ('њ', "nj"),
+    ('о', "o"),
+    ('п', "p"),
+    ('р', "r"),
+    ('с', "s"),
+    ('т', "t"),
+    ('ћ', "c"),
+    ('ђ', "dj"),
+    ('у', "u"),
+    ('ў', "u"),
+    ('ф', "f"),
+    ('х', "kh"),
+    ('ц', "ts"),
+    ('ч', "ch"),
+    ('џ', "dz"),
+    ('ш', "sh"),
+    ('щ', "shch"),
+    ('ъ', ""),
+    ('ь', ""),
+    ('ю', "yu"),
+    ('я', "ya"),
+];
+
+/// Ukrainian, the official transliteration of 2010.
+const UKRAINIAN: Table = &[
+    ('г', "h"),
+    ('ґ', "g"),
+    ('е', "e"),
+    ('є', "ie"),
+    ('и', "y"),
+    ('і', "i"),
+    ('ї', "i"),
+    ('й', "i"),
+    ('ю', "iu"),
+    ('я', "ia"),
+];
 
-fn map_german_umlauts(r: char) -> Lower {
-    let lower = r.to_lowercase().next().unwrap();
-    match lower {
-        'ä' => Lower::Two('a', 'e'),
-        'ö' => Lower::Two('o', 'e'),
-        'ü' => Lower::Two('u', 'e'),
-        'ß' => Lower::Two('s', 's'),
-        _ => Lower::One(lower),
+/// Ukrainian letters spelled differently at the start of a word.
+const UKRAINIAN_INITIAL: Table = &[
+    ('є', "ye"),
+    ('ї', "yi"),
+    ('й', "y"),
+    ('ю', "yu"),
+    ('я', "ya"),
+];
+
+/// Serbian, like the Serbian Latin alphabet without diacritics.
+const SERBIAN: Table = &[
+    ('ђ', "dj"),
+    ('ж', "z"),
+    ('ј', "j"),
+    ('љ', "lj"),
+    ('њ', "nj"),
+    ('ћ', "c"),
+    ('х', "h"),
+    ('ц', "c"),
+    ('ч', "c"),
+    ('џ', "dz"),
+    ('ш', "s"),
+];
+
+/// The Cyrillic tables for a name, as word-initial and other letters. Ukrainian and Serbian are
+/// recognized by the letters only they use, other names follow Russian rules.
+fn cyrillic_tables(input: &str) -> (Table, Table) {
+    let uses = |letters: &str| input.to_lowercase().chars().any(|c| letters.contains(c));
+    if uses("јљњћђџ") {
+        (&[], SERBIAN)
+    } else if uses("іїєґ") {
+        (UKRAINIAN_INITIAL, UKRAINIAN)
+    } else {
+        (&[], &[])
     }
 }
 
-fn replace_german_umlauts(input: &str) -> String {
+/// Greek, accents are removed by the decomposition.
+const GREEK: Table = &[
+    ('α', "a"),
+    ('β', "v"),
+    ('γ', "g"),
+    ('δ', "d"),
+    ('ε', "e"),
+    ('ζ', "z"),
+    ('η', "i"),
+    ('ι', "i"),
+    ('θ', "th"),
+    ('κ', "k"),
+    ('λ', "l"),
+    ('μ', "m"),
+    ('ν', "n"),
+    ('ξ', "x"),
+    ('ο', "o"),
+    ('ω', "o"),
+    ('π', "p"),
+    ('ρ', "r"),
+    ('σ', "s"),
+    ('ς', "s"),
+    ('τ', "t"),
+    ('υ', "y"),
+    ('φ', "f"),
+    ('χ', "ch"),
+    ('ψ', "ps"),
+];
+
+/// Transliterations of lowercase letters that NFKD does not reduce to ASCII.
+fn transliterate(c: char, tables: &[Table]) -> Option<&'static str> {
+    tables
+        .iter()
+        .copied()
+        .flatten()
+        .find_map(|&(from, to)| (from == c).then_some(to))
+}
+
+fn transliterate_all(input: &str) -> String {
+    let (initial, cyrillic) = cyrillic_tables(input);
+    let tables = [GERMAN, LATIN, cyrillic, RUSSIAN, GREEK];
     let mut result = String::with_capacity(input.len());
-    for c in input.chars() {
-        match map_german_umlauts(c) {
-            Lower::One(c) => result.push(c),
-            Lower::Two(a, b) => {
-                result.push(a);
-                result.push(b);
+    let mut word_start = true;
+    for c in input.chars().flat_map(char::to_lowercase) {
+        let found = word_start
+            .then(|| transliterate(c, &[initial]))
+            .flatten()
+            .or_else(|| transliterate(c, &tables));
+        word_start = !c.is_alphanumeric();
+        if let Some(s) = found {
+            result.push_str(s);
+            continue;
+        }
+        for c in std::iter::once(c).nfkd() {
+            match transliterate(c, &tables) {
+                Some(s) => result.push_str(s),
+                None => result.push(c),
             }
         }
     }
     result
 }
 
+/// FNV-1a, which unlike the std hashers is guaranteed to stay the same.
+fn stable_hash(input: &str) -> u32 {
+    input.bytes().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
 fn remove_matches(input: &str, mut f: impl FnMut(char) -> bool) -> String {
     let mut result = String::with_capacity(input.len());
     for c in input.chars() {
@@ -62,11 +220,31 @@ fn replace_space_and_collapse(input: &str, replacement: char) -> String {
     result
 }
 
+/// A permanent recipe id, independent of the name.
+pub fn generate_id() -> String {
+    format!("{:012x}", rand::random::<u64>() >> 16)
+}
+
+/// Derives a readable id from a recipe name. Letters in scripts without a transliteration table,
+/// e.g. CJK, are replaced by a hash of the name, appended to what could be transliterated. Only
+/// names without any letters or digits result in an empty id.
 pub fn to_id_string(s: &str) -> String {
-    let s = replace_german_umlauts(s);
-    let s: String = s.chars().nfkd().collect();
-    let s = remove_matches(&s, is_not_ok);
-    replace_space_and_collapse(&s, '-')
+    let transliterated = transliterate_all(s);
+    let dropped = transliterated
+        .chars()
+        .any(|c| c.is_alphanumeric() && is_not_ok(c));
+    let id = remove_matches(&transliterated, is_not_ok);
+    let id = replace_space_and_collapse(id.trim(), '-');
+    if !dropped {
+        return id;
+    }
+    let name: String = s.trim().nfc().collect();
+    let hash = stable_hash(&name);
+    if id.is_empty() {
+        format!("r-{hash:08x}")
+    } else {
+        format!("{}-{hash:08x}", id.trim_end_matches('-'))
+    }
 }
 
 #[cfg(test)]
@@ -78,5 +256,20 @@ mod tests {
         assert_eq!(to_id_string("Crêpe"), "crepe");
         assert_eq!(to_id_string("Grünkern"), "gruenkern");
         assert_eq!(to_id_string("Nasi Goreng"), "nasi-goreng");
+        assert_eq!(to_id_string("Борщ"), "borshch");
+        assert_eq!(to_id_string("Вареники з вишнями"), "vareniki-z-vishnyami");
+        assert_eq!(to_id_string("Український борщ"), "ukrainskyi-borshch");
+        assert_eq!(to_id_string("Їжа з яйцями"), "yizha-z-yaitsiamy");
+        assert_eq!(to_id_string("Ћевапчићи"), "cevapcici");
+        assert_eq!(to_id_string("Σπανακόπιτα"), "spanakopita");
+        assert_eq!(to_id_string("Şakşuka ve Çılbır"), "saksuka-ve-cilbir");
+        assert_eq!(to_id_string("Smørrebrød"), "smorrebrod");
+        assert_eq!(to_id_string("麻婆豆腐"), to_id_string("麻婆豆腐 "));
+        assert!(to_id_string("麻婆豆腐").starts_with("r-"));
+        assert_ne!(to_id_string("麻婆豆腐"), to_id_string("寿司"));
+        assert!(to_id_string("Tofu 麻婆").starts_with("tofu-"));
+        assert_ne!(to_id_string("Tofu 麻婆"), to_id_string("Tofu 寿司"));
+        assert_eq!(to_id_string("Kuchen 🎂!"), "kuchen");
+        assert_eq!(to_id_string("!?"), "");
     }
 }
@@ -4,37 +4,61 @@
 #![allow(clippy::module_name_repetitions)]
 
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix_files::Files;
+use actix_web::HttpMessage;
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::from_fn;
 use actix_web::web::Data;
-use actix_web::{App, Error, HttpResponse, HttpServer, middleware, web};
-use config::Environment;
-use notify::{RecursiveMode, Watcher};
-use serde::Deserialize;
+use actix_web::{App, Error, HttpRequest, HttpServer, middleware, web};
+use clap::Parser;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use tokio::sync::RwLock;
-use tracing::metadata::LevelFilter;
-use tracing::{Span, info};
+use tracing::field::Empty;
+use tracing::{Span, error, info};
 use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
-use tracing_subscriber::EnvFilter;
-use tracing_subscriber::fmt::format::FmtSpan;
 
 use crate::auth::Users;
+use crate::catalogue::Catalogue;
+use crate::cli::Command;
 use crate::context::Context;
+use crate::metrics::{METRICS, RequestStart};
+use crate::nutrition::Nutrition;
+use crate::oidc::Oidc;
+use crate::pantry::Pantry;
 use crate::recipes::Recipes;
+use crate::settings::Config;
 use crate::templates::Templates;
+use crate::tls::Certificates;
 
 mod auth;
+mod catalogue;
+mod cli;
 mod context;
+mod csrf;
+mod duration;
 mod error;
+mod i18n;
 mod id;
+mod metrics;
 mod middlewares;
+mod nutrition;
+mod oidc;
+mod pantry;
 mod recipe;
 mod recipes;
 mod routes;
+mod settings;
+mod steps;
+mod store;
+mod telemetry;
 mod templates;
+mod tls;
+mod token;
 mod unit;
 
 #[derive(Default)]
@@ -42,104 +66,267 @@ pub(crate) struct DomainRootSpanBuilder;
 
 impl RootSpanBuilder for DomainRootSpanBuilder {
     fn on_request_start(request: &ServiceRequest) -> Span {
-        tracing_actix_web::root_span!(request)
+        request
+            .extensions_mut()
+            .insert(RequestStart(Instant::now()));
+        tracing_actix_web::root_span!(request, user_id = Empty, recipe_id = Empty)
     }
 
     fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        if let Ok(response) = outcome {
+            let request = response.request();
+            if let Some(id) = request.match_info().get("recipe") {
+                span.record("recipe_id", id);
+            }
+            if let Some(RequestStart(start)) = request.extensions().get() {
+                METRICS.record_request(
+                    request.method().as_str(),
+                    request.match_pattern().as_deref().unwrap_or("unmatched"),
+                    response.status().as_u16(),
+                    start.elapsed(),
+                );
+            }
+        }
         DefaultRootSpanBuilder::on_request_end(span, outcome);
     }
 }
 
-pub(crate) fn setup_tracing() {
-    tracing_subscriber::fmt()
-        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .init();
-}
-
-#[derive(Deserialize)]
-struct Config {
-    address: String,
-    cookies_key: String,
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// The configuration file.
+    #[arg(long, global = true, default_value = "Recipes.toml")]
+    config: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-impl Config {
-    fn load(path: PathBuf) -> Result<Config, config::ConfigError> {
-        let config = config::Config::builder()
-            .add_source(config::File::from(path))
-            .add_source(Environment::with_prefix("RECIPES"))
-            .build()?;
-
-        config.try_deserialize()
+async fn run(config: Config, command: Option<Command>) -> std::io::Result<()> {
+    match command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        command => cli::run(config, command).await,
     }
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    setup_tracing();
+async fn serve(config: Config) -> std::io::Result<()> {
+    let session_duration = config.session_duration();
     let Config {
         address,
         cookies_key,
-    } = Config::load("Recipes.toml".into()).unwrap();
+        paths,
+        bcrypt_cost,
+        open_registration,
+        tls,
+        oidc,
+        ..
+    } = config;
 
-    let recipes = Recipes::load_dir(Path::new("recipes")).await;
-    let templates = RwLock::new(Templates::load_directory(PathBuf::from("templates/")).await);
-    let users = Users::load(Path::new("users.json").into()).await;
+    let recipes = Recipes::load_dir(&paths.recipes).await?;
+    let templates = RwLock::new(Templates::load_directory(paths.templates.clone()).await?);
+    let users = Users::load(paths.users, bcrypt_cost).await?;
+    let nutrition = Nutrition::load(paths.nutrition.clone()).await?;
+    let catalogue = Catalogue::load(paths.catalogue).await?;
+    let pantry = Pantry::load(paths.pantry).await?;
     let context = Data::new(Context {
         templates,
+        template_error: RwLock::new(None),
         recipes,
         users,
+        nutrition,
+        catalogue,
+        pantry,
+        open_registration,
+        oidc: oidc.map(Oidc::new),
     });
 
-    let _watcher = {
-        let (rx, mut tx) = tokio::sync::watch::channel(());
-
-        let mut watcher = notify::recommended_watcher(move |res| match res {
-            Ok(_) => {
-                let _ = rx.send(());
-            }
-            Err(e) => info!("watch error: {:?}", e),
-        })
-        .unwrap();
-
-        watcher
-            .watch(Path::new("templates"), RecursiveMode::Recursive)
-            .unwrap();
-
+    let _templates_watcher = {
+        let (watcher, mut changes) = watch(&[&paths.templates], RecursiveMode::Recursive)?;
         let context = (*context).clone();
+        let templates_dir = paths.templates;
         tokio::spawn(async move {
-            while let Ok(()) = tx.changed().await {
+            while let Ok(()) = changes.changed().await {
                 tokio::time::sleep(Duration::from_millis(200)).await;
-                tx.mark_unchanged();
+                changes.mark_unchanged();
                 info!("Reloading templates");
-                let templates = Templates::load_directory(PathBuf::from("templates/")).await;
-                *context.templates.write().await = templates;
+                match Templates::load_directory(templates_dir.clone()).await {
+                    Ok(templates) => {
+                        *context.templates.write().await = templates;
+                        *context.template_error.write().await = None;
+                        METRICS.record_template_reload(true);
+                    }
+                    Err(e) => {
+                        error!("Failed to reload templates: {e}");
+                        *context.template_error.write().await = Some(e.to_string());
+                        METRICS.record_template_reload(false);
+                    }
+                }
             }
         });
-
         watcher
     };
+    let _nutrition_watcher = watch_nutrition(&context, paths.nutrition)?;
 
+    let static_files = paths.static_files;
     let server = HttpServer::new(move || {
-        let cookies_middleware = middlewares::identity();
-        let session_middleware = middlewares::session(cookies_key.as_bytes());
+        let cookies_middleware = middlewares::identity(session_duration);
+        let session_middleware = middlewares::session(cookies_key.as_bytes(), session_duration);
         App::new()
             .app_data(context.clone())
+            .wrap(from_fn(csrf::verify))
+            .wrap(from_fn(error::error_page))
             .wrap(middlewares::tracing())
             .wrap(middleware::Compress::default())
             .wrap(cookies_middleware)
             .wrap(session_middleware)
-            .service(Files::new("/static", "./static"))
+            .service(Files::new("/static", &static_files))
             .configure(routes::configure)
-            .default_service(web::to(HttpResponse::NotFound))
+            .default_service(web::to(routes::not_found))
     });
 
     info!("Connecting to {}", address);
-    let server = server.bind(address)?;
+    let Some(tls) = tls else {
+        return server.bind(address)?.run().await;
+    };
+
+    if let Some(redirect_address) = &tls.redirect_address {
+        redirect_to_https(redirect_address, tls::port_of(&address))?;
+    }
+
+    let certificates = Certificates::load(tls)?;
+    let server_config = certificates.server_config()?;
+    let _certificates_watcher = watch_certificates(&certificates)?;
+
+    server.bind_rustls_0_23(address, server_config)?.run().await
+}
+
+/// Spawns a plain HTTP server redirecting every request to HTTPS.
+fn redirect_to_https(address: &str, https_port: Option<u16>) -> std::io::Result<()> {
+    info!("Redirecting {} to HTTPS", address);
+    let redirect = HttpServer::new(move || {
+        App::new()
+            .wrap(middlewares::tracing())
+            .default_service(web::to(move |req: HttpRequest| async move {
+                let host = req.connection_info().host().to_owned();
+                let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+                tls::redirect(&host, https_port, path)
+            }))
+    })
+    .bind(address)?
+    .run();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = redirect.await {
+            error!("Redirect server failed: {e}");
+        }
+    });
+    Ok(())
+}
+
+fn watch_certificates(certificates: &Arc<Certificates>) -> std::io::Result<RecommendedWatcher> {
+    // Watch the directories, since certificates are usually replaced instead of modified.
+    let mut directories: Vec<_> = certificates
+        .paths()
+        .iter()
+        .map(|p| match p.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        })
+        .collect();
+    directories.dedup();
+    let (watcher, mut changes) = watch(&directories, RecursiveMode::NonRecursive)?;
+    let certificates = certificates.clone();
+    tokio::spawn(async move {
+        while let Ok(()) = changes.changed().await {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            changes.mark_unchanged();
+            info!("Reloading certificates");
+            if let Err(e) = certificates.reload() {
+                error!("Failed to reload certificates: {e}");
+            }
+        }
+    });
+    Ok(watcher)
+}
+
+/// Reloads the nutrition database after the `nutrition` commands changed it.
+fn watch_nutrition(context: &Data<Context>, path: PathBuf) -> std::io::Result<RecommendedWatcher> {
+    // Watch the directory, since the file may not exist yet.
+    let directory = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let (watcher, mut changes) = watch(&[directory], RecursiveMode::NonRecursive)?;
+    let context = (*context).clone();
+    let modified_time = |path: PathBuf| async move {
+        tokio::fs::metadata(path)
+            .await
+            .and_then(|m| m.modified())
+            .ok()
+    };
+    tokio::spawn(async move {
+        let mut modified = modified_time(path.clone()).await;
+        while let Ok(()) = changes.changed().await {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            changes.mark_unchanged();
+            // Other files in the directory change as well.
+            let now = modified_time(path.clone()).await;
+            if now == modified {
+                continue;
+            }
+            modified = now;
+            info!("Reloading the nutrition database");
+            if let Err(e) = context.nutrition.reload().await {
+                error!("Failed to reload the nutrition database: {e}");
+            }
+        }
+    });
+    Ok(watcher)
+}
+
+/// Notifies about changes of the files in `paths`.
+fn watch(
+    paths: &[&Path],
+    mode: RecursiveMode,
+) -> std::io::Result<(RecommendedWatcher, tokio::sync::watch::Receiver<()>)> {
+    let (sender, receiver) = tokio::sync::watch::channel(());
+
+    let mut watcher = notify::recommended_watcher(move |res| match res {
+        Ok(_) => {
+            let _ = sender.send(());
+        }
+        Err(e) => info!("watch error: {:?}", e),
+    })
+    .map_err(|e| std::io::Error::other(format!("Failed to watch files: {e}")))?;
+
+    for path in paths {
+        watcher.watch(path, mode).map_err(|e| {
+            std::io::Error::other(format!("Failed to watch {}: {e}", path.display()))
+        })?;
+    }
 
-    server.run().await
+    Ok((watcher, receiver))
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let config = match Config::load(&args.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load config {}: {e}", args.config.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let _telemetry = match telemetry::setup(&config.logging) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match actix_web::rt::System::new().block_on(run(config, args.command)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
 }
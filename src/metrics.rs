@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the request duration histogram buckets in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Stored in the request extensions when the request starts.
+pub struct RequestStart(pub Instant);
+
+#[derive(Default)]
+struct Route {
+    statuses: BTreeMap<u16, u64>,
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Counters exposed in the Prometheus text format.
+pub struct Metrics {
+    /// Keyed by method and route pattern.
+    requests: Mutex<BTreeMap<(String, String), Route>>,
+    template_reloads: AtomicU64,
+    template_reload_failures: AtomicU64,
+    storage_write_failures: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Metrics {
+        Metrics {
+            requests: Mutex::new(BTreeMap::new()),
+            template_reloads: AtomicU64::new(0),
+            template_reload_failures: AtomicU64::new(0),
+            storage_write_failures: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        let mut requests = self.requests.lock().unwrap();
+        let route = requests
+            .entry((method.to_owned(), route.to_owned()))
+            .or_default();
+        *route.statuses.entry(status).or_default() += 1;
+        let seconds = duration.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&b| seconds <= b) {
+            route.buckets[i] += 1;
+        }
+        route.count += 1;
+        route.sum += seconds;
+    }
+
+    pub fn record_template_reload(&self, success: bool) {
+        if success {
+            self.template_reloads.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.template_reload_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_storage_write_failure(&self) {
+        self.storage_write_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, recipes: usize, users: usize) -> String {
+        let mut out = String::new();
+        let requests = self.requests.lock().unwrap();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Handled HTTP requests.",
+        );
+        for ((method, route), r) in requests.iter() {
+            for (status, count) in &r.statuses {
+                let labels = format!("{},status=\"{status}\"", route_labels(method, route));
+                writeln!(out, "recipes_http_requests_total{{{labels}}} {count}").unwrap();
+            }
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time until the response headers were ready.",
+        );
+        for ((method, route), r) in requests.iter() {
+            let labels = route_labels(method, route);
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(r.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "recipes_http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                )
+                .unwrap();
+            }
+            let (count, sum) = (r.count, r.sum);
+            writeln!(
+                out,
+                "recipes_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {count}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "recipes_http_request_duration_seconds_sum{{{labels}}} {sum}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "recipes_http_request_duration_seconds_count{{{labels}}} {count}"
+            )
+            .unwrap();
+        }
+        drop(requests);
+
+        header(&mut out, "recipes", "gauge", "Stored recipes.");
+        writeln!(out, "recipes_recipes {recipes}").unwrap();
+        header(&mut out, "users", "gauge", "Registered users.");
+        writeln!(out, "recipes_users {users}").unwrap();
+
+        header(
+            &mut out,
+            "template_reloads_total",
+            "counter",
+            "Template reloads.",
+        );
+        let reloads = self.template_reloads.load(Ordering::Relaxed);
+        let failures = self.template_reload_failures.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "recipes_template_reloads_total{{result=\"success\"}} {reloads}"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "recipes_template_reloads_total{{result=\"failure\"}} {failures}"
+        )
+        .unwrap();
+
+        header(
+            &mut out,
+            "storage_write_failures_total",
+            "counter",
+            "Failed writes of recipe or user files.",
+        );
+        let failures = self.storage_write_failures.load(Ordering::Relaxed);
+        writeln!(out, "recipes_storage_write_failures_total {failures}").unwrap();
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP recipes_{name} {help}").unwrap();
+    writeln!(out, "# TYPE recipes_{name} {kind}").unwrap();
+}
+
+fn route_labels(method: &str, route: &str) -> String {
+    format!("method=\"{}\",route=\"{}\"", escape(method), escape(route))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_render() {
+    let metrics = Metrics::new();
+    metrics.record_request("GET", "/recipe/{recipe}", 200, Duration::from_millis(20));
+    metrics.record_request("GET", "/recipe/{recipe}", 404, Duration::from_millis(200));
+    metrics.record_template_reload(true);
+    metrics.record_storage_write_failure();
+
+    let text = metrics.render(2, 1);
+    for line in [
+        r#"recipes_http_requests_total{method="GET",route="/recipe/{recipe}",status="200"} 1"#,
+        r#"recipes_http_requests_total{method="GET",route="/recipe/{recipe}",status="404"} 1"#,
+        r#"recipes_http_request_duration_seconds_bucket{method="GET",route="/recipe/{recipe}",le="0.025"} 1"#,
+        r#"recipes_http_request_duration_seconds_bucket{method="GET",route="/recipe/{recipe}",le="0.25"} 2"#,
+        r#"recipes_http_request_duration_seconds_count{method="GET",route="/recipe/{recipe}"} 2"#,
+        "recipes_recipes 2",
+        "recipes_users 1",
+        r#"recipes_template_reloads_total{result="success"} 1"#,
+        "recipes_storage_write_failures_total 1",
+    ] {
+        assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+    }
+}
@@ -8,20 +8,21 @@ use actix_web::cookie::{Key, SameSite};
 use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
-const SESSION_DURATION: Duration = Duration::from_hours(30 * 24);
-
 pub(crate) fn tracing() -> TracingLogger<DomainRootSpanBuilder> {
     TracingLogger::<DomainRootSpanBuilder>::new()
 }
 
-pub(crate) fn identity() -> IdentityMiddleware {
+pub(crate) fn identity(session_duration: Duration) -> IdentityMiddleware {
     IdentityMiddleware::builder()
-        .visit_deadline(Some(SESSION_DURATION))
+        .visit_deadline(Some(session_duration))
         .logout_behavior(LogoutBehavior::PurgeSession)
         .build()
 }
 
-pub(crate) fn session(cookies_key: &[u8]) -> SessionMiddleware<CookieSessionStore> {
+pub(crate) fn session(
+    cookies_key: &[u8],
+    session_duration: Duration,
+) -> SessionMiddleware<CookieSessionStore> {
     SessionMiddleware::builder(CookieSessionStore::default(), Key::from(cookies_key))
         .cookie_name("recipes-session".into())
         .cookie_http_only(true)
@@ -29,7 +30,7 @@ pub(crate) fn session(cookies_key: &[u8]) -> SessionMiddleware<CookieSessionStor
         .cookie_secure(true)
         .cookie_same_site(SameSite::Strict)
         .session_lifecycle(
-            PersistentSession::default().session_ttl(SESSION_DURATION.try_into().unwrap()),
+            PersistentSession::default().session_ttl(session_duration.try_into().unwrap()),
         )
         .build()
 }
@@ -0,0 +1,549 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLockReadGuard;
+use tracing::instrument;
+
+use crate::catalogue::CatalogueData;
+use crate::error::Error;
+use crate::recipe::{FieldError, RawRecipe, bake_string};
+use crate::store::JsonStore;
+use crate::unit::{grams_per_unit, is_piece_unit};
+
+/// Energy and macronutrients, per 100 g in the database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Nutrients {
+    pub energy_kcal: f64,
+    pub protein: f64,
+    pub fat: f64,
+    pub carbohydrates: f64,
+}
+
+impl Nutrients {
+    fn add_scaled(&mut self, other: &Nutrients, factor: f64) {
+        self.energy_kcal += other.energy_kcal * factor;
+        self.protein += other.protein * factor;
+        self.fat += other.fat * factor;
+        self.carbohydrates += other.carbohydrates * factor;
+    }
+
+    /// Whole kilocalories and grams with one decimal.
+    fn rounded(self, divisor: f64) -> Nutrients {
+        let round = |v: f64| (v / divisor * 10.0).round() / 10.0;
+        Nutrients {
+            energy_kcal: (self.energy_kcal / divisor).round(),
+            protein: round(self.protein),
+            fat: round(self.fat),
+            carbohydrates: round(self.carbohydrates),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Food {
+    #[serde(flatten)]
+    pub nutrients: Nutrients,
+    /// Weight of one piece, for ingredients counted without a unit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub piece_grams: Option<f64>,
+}
+
+/// The nutrition of a baked recipe.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NutritionInfo {
+    total: Nutrients,
+    per_serving: Option<Nutrients>,
+    /// Ingredients with an amount that could not be counted.
+    missing: Vec<String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase", from = "NutritionFile")]
+pub struct NutritionData {
+    foods: BTreeMap<String, Food>,
+    /// Lowercase ingredient names and the food they are made of. Ingredients without an entry
+    /// use the food of the same name.
+    pub ingredients: BTreeMap<String, String>,
+    /// Lowercase food names and the first food with that name, to find foods ignoring case.
+    #[serde(skip)]
+    index: HashMap<String, String>,
+}
+
+/// The stored fields of [`NutritionData`], which builds its index from them.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NutritionFile {
+    foods: BTreeMap<String, Food>,
+    #[serde(default)]
+    ingredients: BTreeMap<String, String>,
+}
+
+impl From<NutritionFile> for NutritionData {
+    fn from(file: NutritionFile) -> NutritionData {
+        NutritionData::new(file.foods, file.ingredients)
+    }
+}
+
+impl NutritionData {
+    pub fn new(
+        foods: BTreeMap<String, Food>,
+        ingredients: BTreeMap<String, String>,
+    ) -> NutritionData {
+        let mut data = NutritionData {
+            foods,
+            ingredients,
+            index: HashMap::new(),
+        };
+        data.reindex();
+        data
+    }
+
+    /// Rebuilds the index after foods were added.
+    fn reindex(&mut self) {
+        self.index.clear();
+        for name in self.foods.keys() {
+            self.index
+                .entry(name.to_lowercase())
+                .or_insert_with(|| name.clone());
+        }
+    }
+
+    fn food(&self, name: &str) -> Option<&Food> {
+        self.foods.get(name).or_else(|| {
+            let name = self.index.get(&name.to_lowercase())?;
+            self.foods.get(name)
+        })
+    }
+
+    /// The food of an ingredient, preferring the overrides of the recipe. Falls back to the
+    /// canonical name of the catalogue, so plurals and synonyms need no mapping of their own.
+    fn food_of(
+        &self,
+        overrides: &BTreeMap<&str, &str>,
+        ingredient: &str,
+        catalogue: &CatalogueData,
+    ) -> Option<&Food> {
+        let canonical = catalogue.canonical(ingredient);
+        let food = overrides
+            .get(ingredient)
+            .copied()
+            .or_else(|| {
+                self.ingredients
+                    .get(&ingredient.to_lowercase())
+                    .map(String::as_str)
+            })
+            .or_else(|| self.ingredients.get(&canonical).map(String::as_str))
+            .unwrap_or(ingredient);
+        self.food(food).or_else(|| self.food(&canonical))
+    }
+
+    /// Sums up all ingredients with a known food and an amount in grams or pieces, with the
+    /// ingredients of referenced recipes instead of the references. `None` if no ingredient
+    /// could be counted.
+    pub fn calculate(
+        &self,
+        recipe: &RawRecipe,
+        catalogue: &CatalogueData,
+        recipes: &HashMap<String, RawRecipe>,
+    ) -> Option<NutritionInfo> {
+        let mut total = Nutrients::default();
+        let mut counted = false;
+        let mut missing = Vec::new();
+        let overrides = overrides(recipe, recipes);
+        for ingredient in recipe.expanded_ingredients(recipes) {
+            let Ok(amount) = f64::from_str(&ingredient.amount) else {
+                continue;
+            };
+            let food = self.food_of(&overrides, &ingredient.name, catalogue);
+            let unit = ingredient.unit.as_deref();
+            let density = catalogue.density(&ingredient.name);
+            let grams = food.and_then(|food| match unit.and_then(|u| grams_per_unit(u, density)) {
+                Some(factor) => Some(amount * factor),
+                None if is_piece_unit(unit) => food.piece_grams.map(|g| amount * g),
+                None => None,
+            });
+            match (food, grams) {
+                (Some(food), Some(grams)) => {
+                    total.add_scaled(&food.nutrients, grams / 100.0);
+                    counted = true;
+                }
+                _ => missing.push(bake_string(&ingredient.name)),
+            }
+        }
+        counted.then(|| NutritionInfo {
+            total: total.rounded(1.0),
+            per_serving: recipe
+                .servings
+                .filter(|&s| s > 0)
+                .map(|s| total.rounded(f64::from(s))),
+            missing,
+        })
+    }
+
+    /// Checks that the overrides of a recipe name existing foods.
+    pub fn validate(&self, recipe: &RawRecipe) -> Vec<FieldError> {
+        recipe
+            .nutrition_foods
+            .values()
+            .filter(|food| self.food(food).is_none())
+            .map(|_| FieldError {
+                path: "NutritionFoods".into(),
+                code: "unknown-food",
+            })
+            .take(1)
+            .collect()
+    }
+}
+
+/// Names of the CSV columns to import.
+pub struct Columns<'a> {
+    pub name: &'a str,
+    pub energy_kcal: &'a str,
+    pub protein: &'a str,
+    pub fat: &'a str,
+    pub carbohydrates: &'a str,
+    /// Optional, the weight of one piece in grams.
+    pub piece_grams: &'a str,
+}
+
+/// Reads foods from a food composition table. Values are per 100 g and may use a decimal comma.
+/// Returns the foods and the number of skipped rows without valid values.
+pub fn read_csv(
+    reader: impl Read,
+    delimiter: u8,
+    columns: &Columns<'_>,
+) -> Result<(Vec<(String, Food)>, usize), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let index = |column: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(column))
+            .ok_or_else(|| format!("Missing column {column:?}"))
+    };
+    let [name, energy_kcal, protein, fat, carbohydrates] = [
+        columns.name,
+        columns.energy_kcal,
+        columns.protein,
+        columns.fat,
+        columns.carbohydrates,
+    ]
+    .map(index);
+    let (name, energy_kcal, protein, fat, carbohydrates) =
+        (name?, energy_kcal?, protein?, fat?, carbohydrates?);
+    let piece_grams = index(columns.piece_grams).ok();
+
+    let mut foods = Vec::new();
+    let mut skipped = 0;
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let value = |i: usize| record.get(i).and_then(parse_number);
+        let food_name = record.get(name).map(str::trim).unwrap_or_default();
+        match (
+            value(energy_kcal),
+            value(protein),
+            value(fat),
+            value(carbohydrates),
+        ) {
+            (Some(energy_kcal), Some(protein), Some(fat), Some(carbohydrates))
+                if !food_name.is_empty() =>
+            {
+                let nutrients = Nutrients {
+                    energy_kcal,
+                    protein,
+                    fat,
+                    carbohydrates,
+                };
+                foods.push((
+                    food_name.to_owned(),
+                    Food {
+                        nutrients,
+                        piece_grams: piece_grams.and_then(value).filter(|&g| g > 0.0),
+                    },
+                ));
+            }
+            _ => skipped += 1,
+        }
+    }
+    Ok((foods, skipped))
+}
+
+/// Parses values like `12,5`, `<0.1` or `-` for none. Negative and non-finite values are
+/// rejected.
+fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim().trim_start_matches('<').replace(',', ".");
+    match s.as_str() {
+        "-" | "tr" | "Tr" => Some(0.0),
+        _ => f64::from_str(&s)
+            .ok()
+            .filter(|n| n.is_finite() && *n >= 0.0),
+    }
+}
+
+pub struct Nutrition {
+    store: JsonStore<NutritionData>,
+}
+
+impl Nutrition {
+    /// Starts with an empty database if the file does not exist yet.
+    pub async fn load(path: PathBuf) -> std::io::Result<Nutrition> {
+        Ok(Nutrition {
+            store: JsonStore::load(path).await?,
+        })
+    }
+
+    pub async fn data(&self) -> RwLockReadGuard<'_, NutritionData> {
+        self.store.read().await
+    }
+
+    /// Reads the database again, after the `nutrition` commands changed it.
+    pub async fn reload(&self) -> std::io::Result<()> {
+        self.store.reload().await
+    }
+
+    /// Adds the foods, replacing those with the same name but keeping their piece weight if the
+    /// new one has none.
+    #[instrument(skip(self, foods))]
+    pub async fn import(&self, foods: Vec<(String, Food)>) -> Result<(), Error> {
+        self.store
+            .update(|data| {
+                for (name, mut food) in foods {
+                    if let Some(old) = data.foods.get(&name) {
+                        food.piece_grams = food.piece_grams.or(old.piece_grams);
+                    }
+                    data.foods.insert(name, food);
+                }
+                data.reindex();
+                Ok(())
+            })
+            .await
+    }
+
+    /// Sets the food of an ingredient for all recipes, or removes the mapping.
+    #[instrument(skip(self))]
+    pub async fn map(&self, ingredient: &str, food: Option<String>) -> Result<(), Error> {
+        self.store
+            .update(|data| {
+                let ingredient = ingredient.trim().to_lowercase();
+                match food {
+                    Some(food) => {
+                        if data.food(&food).is_none() {
+                            return Err(Error::NotFound);
+                        }
+                        data.ingredients.insert(ingredient, food);
+                    }
+                    None => {
+                        data.ingredients.remove(&ingredient);
+                    }
+                }
+                Ok(())
+            })
+            .await
+    }
+}
+
+/// The food overrides of a recipe and of the recipes it references, the closer recipe winning.
+fn overrides<'a>(
+    recipe: &'a RawRecipe,
+    recipes: &'a HashMap<String, RawRecipe>,
+) -> BTreeMap<&'a str, &'a str> {
+    let mut overrides = BTreeMap::new();
+    let mut visited = HashSet::new();
+    let mut pending = VecDeque::from([recipe]);
+    while let Some(recipe) = pending.pop_front() {
+        for (ingredient, food) in &recipe.nutrition_foods {
+            overrides
+                .entry(ingredient.as_str())
+                .or_insert(food.as_str());
+        }
+        pending.extend(
+            recipe
+                .references()
+                .filter(|id| visited.insert(*id))
+                .filter_map(|id| recipes.get(id)),
+        );
+    }
+    overrides
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use tempfile::TempDir;
+
+    use super::{Columns, Food, Nutrients, Nutrition, NutritionData, read_csv};
+    use crate::catalogue::{CatalogueData, CatalogueEntry};
+    use crate::error::Error;
+    use crate::recipe::{Ingredient, IngredientsSection, RawRecipe};
+
+    fn ingredient(name: &str, amount: &str, unit: Option<&str>) -> Ingredient {
+        Ingredient {
+            name: name.into(),
+            amount: amount.into(),
+            unit: unit.map(Into::into),
+            recipe: None,
+        }
+    }
+
+    fn nutrients(energy_kcal: f64, protein: f64, fat: f64, carbohydrates: f64) -> Nutrients {
+        Nutrients {
+            energy_kcal,
+            protein,
+            fat,
+            carbohydrates,
+        }
+    }
+
+    #[test]
+    fn test_calculate() {
+        let food = |energy_kcal, piece_grams| Food {
+            nutrients: nutrients(energy_kcal, 10.0, 1.0, 70.0),
+            piece_grams,
+        };
+        let mut data = NutritionData::new(
+            BTreeMap::from([
+                ("Weizenmehl".into(), food(350.0, None)),
+                ("Ei".into(), food(150.0, Some(60.0))),
+                ("Zucker".into(), food(400.0, None)),
+            ]),
+            BTreeMap::from([("mehl".into(), "Weizenmehl".into())]),
+        );
+        let mut recipe = RawRecipe {
+            name: "Kuchen".into(),
+            ingredients_sections: vec![IngredientsSection {
+                ingredients: vec![
+                    ingredient("Mehl", "0.5", Some("kg")),
+                    ingredient("Eier", "2", None),
+                    ingredient("Rohrzucker", "100", Some("g")),
+                    ingredient("Milch", "200", Some("ml")),
+                    ingredient("Salz", "", None),
+                ],
+                ..IngredientsSection::default()
+            }],
+            servings: Some(4),
+            nutrition_foods: BTreeMap::from([("Eier".into(), "Ei".into())]),
+            ..RawRecipe::default()
+        };
+        recipe
+            .nutrition_foods
+            .insert("Rohrzucker".into(), "zucker".into());
+
+        let info = data
+            .calculate(&recipe, &CatalogueData::default(), &HashMap::new())
+            .unwrap();
+        assert_eq!(info.total, nutrients(2330.0, 72.0, 7.2, 504.0));
+        assert_eq!(info.per_serving, Some(nutrients(583.0, 18.0, 1.8, 126.0)));
+        assert_eq!(info.missing, ["Milch"]);
+        let catalogue = CatalogueData::new(BTreeMap::from([(
+            "milch".into(),
+            CatalogueEntry {
+                density: Some(1.03),
+                ..CatalogueEntry::default()
+            },
+        )]));
+        data.foods.insert("Milch".into(), food(64.0, None));
+        data.reindex();
+        let info = data
+            .calculate(&recipe, &catalogue, &HashMap::new())
+            .unwrap();
+        assert_eq!(info.total, nutrients(2462.0, 92.6, 9.3, 648.2));
+        assert!(info.missing.is_empty());
+        assert!(data.validate(&recipe).is_empty());
+
+        // Referenced recipes count with their own overrides, here twice.
+        let torte = RawRecipe {
+            name: "Torte".into(),
+            ingredients_sections: vec![IngredientsSection {
+                ingredients: vec![Ingredient {
+                    recipe: Some("kuchen".into()),
+                    ..ingredient("Kuchen", "2", None)
+                }],
+                ..IngredientsSection::default()
+            }],
+            servings: None,
+            nutrition_foods: BTreeMap::new(),
+            ..recipe.clone()
+        };
+        let recipes = HashMap::from([("kuchen".into(), recipe.clone())]);
+        let info = data.calculate(&torte, &catalogue, &recipes).unwrap();
+        assert_eq!(info.total, nutrients(4924.0, 185.2, 18.5, 1296.4));
+        assert_eq!(info.per_serving, None);
+
+        recipe
+            .nutrition_foods
+            .insert("Milch".into(), "Vollmilch".into());
+        assert_eq!(data.validate(&recipe)[0].code, "unknown-food");
+    }
+
+    #[test]
+    fn test_read_csv() {
+        let csv = "Lebensmittel;kcal;Eiweiß;Fett;KH;Stück\n\
+                   Weizenmehl Type 405;343;9,8;1;71,4\n\
+                   Ei;137;11,9;9,3;1,5;60\n\
+                   Wasser;0;-;-;<0,1\n\
+                   Unbekannt;;;;\n\
+                   Kaputt;NaN;1;1;1\n\
+                   Unendlich;1;inf;1;1\n\
+                   Negativ;1;1;-3;1\n\
+                   Riesig;1;1;1;1e400\n";
+        let columns = Columns {
+            name: "lebensmittel",
+            energy_kcal: "kcal",
+            protein: "Eiweiß",
+            fat: "Fett",
+            carbohydrates: "KH",
+            piece_grams: "stück",
+        };
+        let (foods, skipped) = read_csv(csv.as_bytes(), b';', &columns).unwrap();
+        assert_eq!(skipped, 5);
+        assert_eq!(foods.len(), 3);
+        assert_eq!(foods[0].0, "Weizenmehl Type 405");
+        assert_eq!(foods[0].1.nutrients, nutrients(343.0, 9.8, 1.0, 71.4));
+        assert_eq!(foods[0].1.piece_grams, None);
+        assert_eq!(foods[1].1.piece_grams, Some(60.0));
+        assert_eq!(foods[2].1.nutrients, nutrients(0.0, 0.0, 0.0, 0.1));
+    }
+
+    #[tokio::test]
+    async fn test_import_and_map() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("nutrition.json");
+        let nutrition = Nutrition::load(path.clone()).await.unwrap();
+        let milch = Food {
+            nutrients: nutrients(64.0, 3.4, 3.6, 4.8),
+            piece_grams: None,
+        };
+        nutrition
+            .import(vec![("Vollmilch".into(), milch.clone())])
+            .await
+            .unwrap();
+        nutrition
+            .map(" Milch", Some("VOLLMILCH".into()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            nutrition.map("Sahne", Some("Schlagsahne".into())).await,
+            Err(Error::NotFound)
+        ));
+
+        let server = Nutrition::load(path.clone()).await.unwrap();
+        let data = server.data().await;
+        assert_eq!(data.food("vollMilch"), Some(&milch));
+        assert_eq!(data.ingredients["milch"], "VOLLMILCH");
+        drop(data);
+
+        nutrition.map("Milch", None).await.unwrap();
+        server.reload().await.unwrap();
+        assert!(server.data().await.ingredients.is_empty());
+    }
+}
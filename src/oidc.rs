@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, OnceCell};
+use tracing::{error, instrument};
+
+use crate::auth::Role;
+use crate::error::Error;
+use crate::token;
+
+/// How long a user may take to log in at the provider.
+pub const PENDING_DURATION: Duration = Duration::from_mins(10);
+
+/// How many logins can be in progress at once. Beyond that, the oldest are dropped.
+const MAX_PENDING: usize = 1_000;
+
+/// The cookie tying a login in progress to the browser that started it.
+pub const STATE_COOKIE: &str = "recipes-oidc-state";
+
+#[derive(Clone, Deserialize)]
+pub struct OidcConfig {
+    /// Shown on the login page.
+    #[serde(default = "default_name")]
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// The public URL of `/oidc/callback`.
+    pub redirect_url: String,
+    /// Unknown subjects get a new user with this role. Without it, subjects have to be linked
+    /// to an existing user first.
+    #[serde(default)]
+    pub register_role: Option<Role>,
+}
+
+fn default_name() -> String {
+    "OpenID Connect".into()
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(a) => a == client_id,
+            Audience::Many(a) => a.iter().any(|a| a == client_id),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    exp: u64,
+    nonce: Option<String>,
+    preferred_username: Option<String>,
+}
+
+struct Pending {
+    nonce: String,
+    /// The PKCE code verifier (RFC 7636).
+    verifier: String,
+    link: Option<String>,
+    expires: u64,
+}
+
+/// A user authenticated by the provider.
+pub struct ExternalUser {
+    /// The issuer and subject, unique across providers.
+    pub subject: String,
+    pub preferred_username: Option<String>,
+    /// The local user that started the login and wants the subject linked to their account.
+    pub link: Option<String>,
+}
+
+fn handle_request_error(url: &str, e: &reqwest::Error) -> Error {
+    error!("Request to {url} failed: {e}");
+    Error::Internal
+}
+
+/// The ID token is received directly from the token endpoint, so its signature does not have
+/// to be verified (OIDC Core 1.0, section 3.1.3.7).
+fn decode_claims(id_token: &str) -> Result<Claims, Error> {
+    let payload = id_token.split('.').nth(1).ok_or(Error::Unauthorized)?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| Error::Unauthorized)?;
+    serde_json::from_slice(&payload).map_err(|_| Error::Unauthorized)
+}
+
+/// Authorization code flow against a single OIDC provider.
+///
+/// The state of logins in progress is kept here instead of the session, because the strict
+/// session cookie is not sent when the provider redirects back.
+pub struct Oidc {
+    config: OidcConfig,
+    client: Client,
+    discovery: OnceCell<Discovery>,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Oidc {
+            config,
+            client: Client::new(),
+            discovery: OnceCell::new(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn register_role(&self) -> Option<Role> {
+        self.config.register_role
+    }
+
+    async fn discovery(&self) -> Result<&Discovery, Error> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                async {
+                    self.client
+                        .get(&url)
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await
+                }
+                .await
+                .map_err(|e| handle_request_error(&url, &e))
+            })
+            .await
+    }
+
+    /// Returns the provider URL to send the user to and the state, which the browser has to
+    /// present again in [`STATE_COOKIE`] when it comes back.
+    #[instrument(skip(self), err)]
+    pub async fn authorization_url(&self, link: Option<String>) -> Result<(String, String), Error> {
+        let discovery = self.discovery().await?;
+        let state = token::generate();
+        let nonce = token::generate();
+        let verifier = token::generate();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let mut url = Url::parse(&discovery.authorization_endpoint).map_err(|e| {
+            error!("Invalid authorization endpoint: {e}");
+            Error::Internal
+        })?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", "openid profile")
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let now = token::now();
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, p| p.expires > now);
+        while pending.len() >= MAX_PENDING {
+            let oldest = pending
+                .iter()
+                .min_by_key(|(_, p)| p.expires)
+                .map(|(s, _)| s.clone())
+                .unwrap();
+            pending.remove(&oldest);
+        }
+        pending.insert(
+            state.clone(),
+            Pending {
+                nonce,
+                verifier,
+                link,
+                expires: now + PENDING_DURATION.as_secs(),
+            },
+        );
+        Ok((url.into(), state))
+    }
+
+    /// Exchanges the code the provider redirected back with for the authenticated user.
+    /// `browser_state` is the state from [`STATE_COOKIE`], so a callback URL started by someone
+    /// else does not log the browser in.
+    #[instrument(skip_all, err)]
+    pub async fn authenticate(
+        &self,
+        code: &str,
+        state: &str,
+        browser_state: Option<&str>,
+    ) -> Result<ExternalUser, Error> {
+        if browser_state.is_none_or(|b| token::hash(b) != token::hash(state)) {
+            return Err(Error::Unauthorized);
+        }
+        let now = token::now();
+        let pending = self
+            .pending
+            .lock()
+            .await
+            .remove(state)
+            .filter(|p| p.expires > now)
+            .ok_or(Error::Unauthorized)?;
+        let discovery = self.discovery().await?;
+        let url = &discovery.token_endpoint;
+        let response: TokenResponse = async {
+            self.client
+                .post(url)
+                .form(&[
+                    ("grant_type", "authorization_code"),
+                    ("code", code),
+                    ("redirect_uri", &self.config.redirect_url),
+                    ("client_id", &self.config.client_id),
+                    ("client_secret", &self.config.client_secret),
+                    ("code_verifier", &pending.verifier),
+                ])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+        }
+        .await
+        .map_err(|e| handle_request_error(url, &e))?;
+
+        let claims = decode_claims(&response.id_token)?;
+        let valid = claims.iss.trim_end_matches('/') == self.config.issuer.trim_end_matches('/')
+            && claims.aud.contains(&self.config.client_id)
+            && claims.exp > now
+            && claims.nonce.as_deref() == Some(&pending.nonce);
+        if !valid {
+            return Err(Error::Unauthorized);
+        }
+        Ok(ExternalUser {
+            subject: format!("{} {}", claims.iss, claims.sub),
+            preferred_username: claims.preferred_username,
+            link: pending.link,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::cookie::Cookie;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{LOCATION, SET_COOKIE};
+    use actix_web::web::Data;
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, test, web};
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use reqwest::Url;
+    use serde::Deserialize;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+
+    use super::{Oidc, OidcConfig};
+    use crate::auth::{Role, Users};
+    use crate::routes::tests::{app_with, make_context};
+
+    fn issuer(req: &HttpRequest) -> String {
+        format!("http://{}", req.connection_info().host())
+    }
+
+    #[derive(Deserialize)]
+    struct TokenForm {
+        code_verifier: String,
+    }
+
+    /// Serves discovery and a token endpoint issuing unsigned ID tokens with `nonce`. The code
+    /// verifier of the last token request is put into `verifier`.
+    fn mock_provider(nonce: Arc<Mutex<String>>, verifier: Arc<Mutex<String>>) -> String {
+        let server = HttpServer::new(move || {
+            let nonce = nonce.clone();
+            let verifier = verifier.clone();
+            App::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(|req: HttpRequest| async move {
+                        let issuer = issuer(&req);
+                        HttpResponse::Ok().json(json!({
+                            "authorization_endpoint": format!("{issuer}/authorize"),
+                            "token_endpoint": format!("{issuer}/token"),
+                        }))
+                    }),
+                )
+                .route(
+                    "/token",
+                    web::post().to(move |req: HttpRequest, form: web::Form<TokenForm>| {
+                        let nonce = nonce.lock().unwrap().clone();
+                        *verifier.lock().unwrap() = form.into_inner().code_verifier;
+                        async move {
+                            let claims = json!({
+                                "iss": issuer(&req),
+                                "sub": "subject",
+                                "aud": "recipes",
+                                "exp": u64::MAX,
+                                "nonce": nonce,
+                                "preferred_username": "external",
+                            });
+                            let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+                            HttpResponse::Ok().json(json!({ "id_token": format!("e30.{claims}.") }))
+                        }
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{address}")
+    }
+
+    #[actix_web::test]
+    async fn test_login() {
+        let nonce = Arc::new(Mutex::new(String::new()));
+        let verifier = Arc::new(Mutex::new(String::new()));
+        let issuer = mock_provider(nonce.clone(), verifier.clone());
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("users.json");
+        std::fs::copy("tests/users.json", &path).unwrap();
+        let users = Users::load(path.clone(), 4).await.unwrap();
+        let mut context = make_context(users).await;
+        context.oidc = Some(Oidc::new(OidcConfig {
+            name: "Test".into(),
+            issuer,
+            client_id: "recipes".into(),
+            client_secret: "secret".into(),
+            redirect_url: "http://localhost/oidc/callback".into(),
+            register_role: Some(Role::Reader),
+        }));
+        let app = test::init_service(app_with(Data::new(context))).await;
+
+        let req = test::TestRequest::with_uri("/oidc/login").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        let state_cookie = resp.response().cookies().next().unwrap().into_owned();
+        let location = resp.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let location = Url::parse(location).unwrap();
+        let param = |name| {
+            location
+                .query_pairs()
+                .find(|(k, _)| k == name)
+                .unwrap()
+                .1
+                .into_owned()
+        };
+        *nonce.lock().unwrap() = param("nonce");
+
+        let req = test::TestRequest::with_uri("/oidc/callback?code=code&state=wrong")
+            .cookie(state_cookie.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // A callback URL sent to another browser does not log that browser in.
+        let uri = format!("/oidc/callback?code=code&state={}", param("state"));
+        let req = test::TestRequest::with_uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::with_uri(&uri)
+            .cookie(state_cookie)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.lock().unwrap().as_bytes()));
+        assert_eq!(challenge, param("code_challenge"));
+        let cookie = resp
+            .headers()
+            .get_all(SET_COOKIE)
+            .map(|c| Cookie::parse_encoded(c.to_str().unwrap().to_owned()).unwrap())
+            .find(|c| c.name() == "recipes-session")
+            .unwrap();
+
+        let req = test::TestRequest::with_uri("/login")
+            .cookie(cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(std::str::from_utf8(&body).unwrap().contains("external"));
+        assert_eq!(
+            Users::load(path, 4).await.unwrap().role("external").await,
+            Some(Role::Reader)
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::catalogue::CatalogueData;
+use crate::error::Error;
+use crate::recipe::RawRecipe;
+use crate::store::JsonStore;
+use crate::unit::convert;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PantryItem {
+    pub amount: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+/// The items of one household, keyed by the ingredient name.
+type Items = BTreeMap<String, PantryItem>;
+
+/// How much of an ingredient is on hand, in the given unit. Returns the key of the item, or
+/// `None` if there is no item or its unit cannot be converted.
+pub fn on_hand(
+    items: &Items,
+    name: &str,
+    unit: Option<&str>,
+    catalogue: &CatalogueData,
+) -> Option<(String, f64)> {
+    let canonical = catalogue.canonical(name);
+    let (key, item) = items
+        .iter()
+        .find(|(k, _)| catalogue.canonical(k) == canonical)?;
+    let amount = if item.unit.as_deref() == unit {
+        item.amount
+    } else {
+        convert(
+            item.amount,
+            item.unit.as_deref()?,
+            unit?,
+            catalogue.density(name),
+        )?
+    };
+    Some((key.clone(), amount))
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PantryData {
+    households: BTreeMap<String, Items>,
+}
+
+/// What each household has on hand.
+pub struct Pantry {
+    store: JsonStore<PantryData>,
+}
+
+impl Pantry {
+    /// Starts with empty pantries if the file does not exist yet.
+    pub async fn load(path: PathBuf) -> std::io::Result<Pantry> {
+        Ok(Pantry {
+            store: JsonStore::load(path).await?,
+        })
+    }
+
+    pub async fn items(&self, household: &str) -> Items {
+        let data = self.store.read().await;
+        data.households.get(household).cloned().unwrap_or_default()
+    }
+
+    /// Sets the amount of an ingredient, replacing an item with the same canonical name. Removes
+    /// the item without an item or with an amount of zero.
+    #[instrument(skip(self, catalogue))]
+    pub async fn set(
+        &self,
+        household: &str,
+        name: &str,
+        item: Option<PantryItem>,
+        catalogue: &CatalogueData,
+    ) -> Result<(), Error> {
+        let name = catalogue
+            .normalize(name)
+            .unwrap_or_else(|| name.trim().to_owned());
+        if name.is_empty() {
+            return Err(Error::EmptyName);
+        }
+        if item
+            .as_ref()
+            .is_some_and(|i| !i.amount.is_finite() || i.amount < 0.0)
+        {
+            return Err(Error::InvalidAmount);
+        }
+
+        self.store
+            .update(|data| {
+                let items = data.households.entry(household.to_owned()).or_default();
+                let canonical = catalogue.canonical(&name);
+                items.retain(|k, _| catalogue.canonical(k) != canonical);
+                if let Some(item) = item.filter(|i| i.amount > 0.0) {
+                    items.insert(name, item);
+                }
+                if items.is_empty() {
+                    data.households.remove(household);
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// Takes the ingredients of a recipe, multiplied by `factor`, out of the pantry. Ingredients
+    /// without a number or with units that cannot be converted are left alone. Returns the names
+    /// of the items that were decreased. Referenced recipes are taken along.
+    #[instrument(skip(self, recipe, recipes, catalogue), fields(recipe=%recipe.name))]
+    pub async fn cook(
+        &self,
+        household: &str,
+        recipe: &RawRecipe,
+        factor: f64,
+        recipes: &HashMap<String, RawRecipe>,
+        catalogue: &CatalogueData,
+    ) -> Result<Vec<String>, Error> {
+        if !self.store.read().await.households.contains_key(household) {
+            return Ok(Vec::new());
+        }
+        self.store
+            .update(|data| {
+                let Some(items) = data.households.get_mut(household) else {
+                    return Ok(Vec::new());
+                };
+                let mut taken = Vec::new();
+                for ingredient in recipe.expanded_ingredients(recipes) {
+                    let Ok(amount) = f64::from_str(&ingredient.amount) else {
+                        continue;
+                    };
+                    let unit = ingredient.unit.as_deref();
+                    let Some((key, available)) = on_hand(items, &ingredient.name, unit, catalogue)
+                    else {
+                        continue;
+                    };
+                    let item = items.get_mut(&key).unwrap();
+                    let left = (available - amount * factor).max(0.0);
+                    // Rounded so conversions do not leave amounts like 1.0999999999999999.
+                    item.amount = (item.amount * left / available * 1000.0).round() / 1000.0;
+                    if item.amount == 0.0 {
+                        items.remove(&key);
+                    }
+                    taken.push(key);
+                }
+                if items.is_empty() {
+                    data.households.remove(household);
+                }
+                Ok(taken)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use tempfile::TempDir;
+
+    use super::{Pantry, PantryItem};
+    use crate::catalogue::{CatalogueData, CatalogueEntry};
+    use crate::recipe::{Ingredient, IngredientsSection, RawRecipe};
+
+    #[tokio::test]
+    async fn test_cook() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("pantry.json");
+        let pantry = Pantry::load(path.clone()).await.unwrap();
+        let catalogue = CatalogueData::new(BTreeMap::from([(
+            "Ei".into(),
+            CatalogueEntry {
+                plural: Some("Eier".into()),
+                ..CatalogueEntry::default()
+            },
+        )]));
+        let item = |amount, unit: Option<&str>| {
+            Some(PantryItem {
+                amount,
+                unit: unit.map(Into::into),
+            })
+        };
+        pantry
+            .set("home", "Mehl", item(1.0, Some("kg")), &catalogue)
+            .await
+            .unwrap();
+        pantry
+            .set("home", "eier", item(6.0, None), &catalogue)
+            .await
+            .unwrap();
+        pantry
+            .set("home", "Milch", item(100.0, Some("ml")), &catalogue)
+            .await
+            .unwrap();
+
+        let ingredient = |name: &str, amount: &str, unit: Option<&str>| Ingredient {
+            name: name.into(),
+            amount: amount.into(),
+            unit: unit.map(Into::into),
+            recipe: None,
+        };
+        let recipe = RawRecipe {
+            name: "Pfannkuchen".into(),
+            ingredients_sections: vec![IngredientsSection {
+                ingredients: vec![
+                    ingredient("Mehl", "125", Some("g")),
+                    ingredient("Ei", "2", None),
+                    ingredient("Milch", "1", Some("l")),
+                    ingredient("Salz", "1", Some("Prise")),
+                ],
+                ..IngredientsSection::default()
+            }],
+            ..RawRecipe::default()
+        };
+        let taken = pantry
+            .cook("home", &recipe, 2.0, &HashMap::new(), &catalogue)
+            .await
+            .unwrap();
+        assert_eq!(taken, ["Mehl", "Eier", "Milch"]);
+
+        let pantry = Pantry::load(path).await.unwrap();
+        let items = pantry.items("home").await;
+        assert_eq!(
+            items.into_iter().collect::<Vec<_>>(),
+            [
+                ("Eier".into(), item(2.0, None).unwrap()),
+                ("Mehl".into(), item(0.75, Some("kg")).unwrap()),
+            ]
+        );
+        assert!(pantry.items("other").await.is_empty());
+    }
+}
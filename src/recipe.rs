@@ -1,10 +1,16 @@
-use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
 use std::str::FromStr;
-use std::{collections::HashMap, io::Cursor};
 
 use comrak::{Options, markdown_to_html, options::Extension, options::Parse, options::Render};
 use serde::{Deserialize, Serialize};
 
+use crate::catalogue::CatalogueData;
+use crate::id::to_id_string;
+use crate::nutrition::{NutritionData, NutritionInfo};
+use crate::steps::{Step, bake_instructions};
+use crate::unit::convert;
+
 #[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug, PartialEq, Eq))]
 #[serde(rename_all = "PascalCase")]
@@ -12,17 +18,24 @@ pub struct Ingredient {
     pub name: String,
     pub amount: String,
     pub unit: Option<String>,
+    /// The id of a recipe the ingredient is made by. The amount says how many times the recipe,
+    /// once if it is not a number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<String>,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug, PartialEq, Eq))]
 #[serde(rename_all = "PascalCase")]
 pub struct IngredientsSection {
     pub heading: String,
     pub ingredients: Vec<Ingredient>,
+    /// The id of a recipe whose ingredients are listed before the section's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<String>,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug, PartialEq, Eq))]
 #[serde(rename_all = "PascalCase")]
 pub struct RawRecipe {
@@ -33,16 +46,41 @@ pub struct RawRecipe {
     pub source: String,
     #[serde(default)]
     pub categories: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub servings: Option<u32>,
+    /// Durations in minutes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prep_time: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cook_time: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rest_time: Option<u32>,
+    /// Ingredient names and the food of the nutrition database to use for them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nutrition_foods: BTreeMap<String, String>,
+    /// Slugs of previous names, still redirecting to the recipe.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
 }
 
 impl RawRecipe {
-    pub fn clean(&mut self) {
+    /// Trims all fields and spells known ingredients like the catalogue.
+    pub fn clean(&mut self, catalogue: &CatalogueData) {
         clean(&mut self.name);
         clean(&mut self.description);
+        for (_, reference) in self.references_mut() {
+            *reference = reference
+                .take()
+                .map(|r| r.trim().to_owned())
+                .filter(|r| !r.is_empty());
+        }
         for s in &mut self.ingredients_sections {
             clean(&mut s.heading);
             for i in &mut s.ingredients {
                 clean(&mut i.name);
+                if let Some(name) = catalogue.normalize(&i.name) {
+                    i.name = name;
+                }
                 i.unit = i.unit.as_ref().map(|u| u.trim().to_string());
                 i.amount = i.amount.trim().replace(',', ".");
             }
@@ -50,11 +88,175 @@ impl RawRecipe {
         for i in &mut self.categories {
             clean(i);
         }
+        // Keyed by ingredient names, so spelled like them.
+        self.nutrition_foods = std::mem::take(&mut self.nutrition_foods)
+            .into_iter()
+            .map(|(k, v)| {
+                let k = catalogue
+                    .normalize(&k)
+                    .unwrap_or_else(|| k.trim().to_string());
+                (k, v.trim().to_string())
+            })
+            .filter(|(k, v)| !k.is_empty() && !v.is_empty())
+            .collect();
+    }
+
+    /// The sum of all durations, `None` if none is known.
+    pub fn total_time(&self) -> Option<u32> {
+        [self.prep_time, self.cook_time, self.rest_time]
+            .into_iter()
+            .flatten()
+            .reduce(u32::saturating_add)
+    }
+
+    /// Whether the name or an ingredient contains the query, ignoring case. Ingredients also match
+    /// by their canonical name, so searching for a synonym or plural finds them, and the
+    /// ingredients of referenced recipes match as well as the references themselves.
+    pub fn matches(
+        &self,
+        query: &str,
+        catalogue: &CatalogueData,
+        recipes: &HashMap<String, RawRecipe>,
+    ) -> bool {
+        let query = query.trim().to_lowercase();
+        let canonical = catalogue.canonical(&query);
+        self.name.to_lowercase().contains(&query)
+            || self
+                .sections(recipes)
+                .into_iter()
+                .flat_map(|s| s.ingredients)
+                .chain(self.expanded_ingredients(recipes))
+                .any(|i| {
+                    i.name.to_lowercase().contains(&query)
+                        || catalogue.canonical(&i.name) == canonical
+                })
+    }
+
+    /// Which ingredients, including those of referenced recipes, are covered by the canonical
+    /// names in `on_hand`. Ingredients that appear in several sections count once.
+    pub fn coverage(
+        &self,
+        on_hand: &HashSet<String>,
+        catalogue: &CatalogueData,
+        recipes: &HashMap<String, RawRecipe>,
+        ignore_staples: bool,
+    ) -> Coverage {
+        let mut seen = HashSet::new();
+        let mut coverage = Coverage::default();
+        for ingredient in self.expanded_ingredients(recipes) {
+            let canonical = catalogue.canonical(&ingredient.name);
+            if !seen.insert(canonical.clone())
+                || (ignore_staples && catalogue.is_staple(&ingredient.name))
+            {
+                continue;
+            }
+            if on_hand.contains(&canonical) {
+                coverage.covered += 1;
+            } else {
+                coverage.missing.push(ingredient.name);
+            }
+        }
+        coverage
+    }
+
+    /// The ids of the recipes referenced by sections and ingredients.
+    pub fn references(&self) -> impl Iterator<Item = &str> {
+        self.ingredients_sections
+            .iter()
+            .flat_map(|s| {
+                s.recipe
+                    .iter()
+                    .chain(s.ingredients.iter().flat_map(|i| &i.recipe))
+            })
+            .map(String::as_str)
+    }
+
+    /// The references of sections and ingredients, with their paths like in [`FieldError`].
+    pub fn references_mut(&mut self) -> Vec<(String, &mut Option<String>)> {
+        let mut references = Vec::new();
+        for (i, section) in self.ingredients_sections.iter_mut().enumerate() {
+            references.push((
+                format!("IngredientsSections.{i}.Recipe"),
+                &mut section.recipe,
+            ));
+            for (j, ingredient) in section.ingredients.iter_mut().enumerate() {
+                let path = format!("IngredientsSections.{i}.Ingredients.{j}.Recipe");
+                references.push((path, &mut ingredient.recipe));
+            }
+        }
+        references
+    }
+
+    /// The sections with the ingredients of the recipes they reference in front of their own.
+    pub fn sections(&self, recipes: &HashMap<String, RawRecipe>) -> Vec<IngredientsSection> {
+        self.sections_at(recipes, 0)
+    }
+
+    fn sections_at(
+        &self,
+        recipes: &HashMap<String, RawRecipe>,
+        depth: usize,
+    ) -> Vec<IngredientsSection> {
+        let mut sections = self.ingredients_sections.clone();
+        for section in &mut sections {
+            let referenced = section.recipe.as_ref().and_then(|id| recipes.get(id));
+            if let Some(other) = referenced.filter(|_| depth < MAX_REFERENCE_DEPTH) {
+                let mut ingredients: Vec<_> = other
+                    .sections_at(recipes, depth + 1)
+                    .into_iter()
+                    .flat_map(|s| s.ingredients)
+                    .collect();
+                ingredients.append(&mut section.ingredients);
+                section.ingredients = ingredients;
+            }
+        }
+        sections
+    }
+
+    /// All ingredients, with the ingredients of referenced recipes instead of the references. An
+    /// amount without a unit, like `2`, takes the referenced recipe that many times, otherwise
+    /// it is taken once.
+    pub fn expanded_ingredients(&self, recipes: &HashMap<String, RawRecipe>) -> Vec<Ingredient> {
+        self.expanded_at(recipes, 1.0, 0)
     }
 
-    pub fn bake(self) -> BakedRecipe {
-        let ingredients_sections = self
-            .ingredients_sections
+    fn expanded_at(
+        &self,
+        recipes: &HashMap<String, RawRecipe>,
+        factor: f64,
+        depth: usize,
+    ) -> Vec<Ingredient> {
+        let mut expanded = Vec::new();
+        for ingredient in self
+            .sections_at(recipes, depth)
+            .into_iter()
+            .flat_map(|s| s.ingredients)
+        {
+            match ingredient.recipe.as_ref().and_then(|id| recipes.get(id)) {
+                Some(other) if depth < MAX_REFERENCE_DEPTH => {
+                    let times = match ingredient.unit.as_deref() {
+                        None | Some("") => f64::from_str(&ingredient.amount).unwrap_or(1.0),
+                        Some(_) => 1.0,
+                    };
+                    expanded.extend(other.expanded_at(recipes, factor * times, depth + 1));
+                }
+                _ => expanded.push(Ingredient {
+                    amount: scale_amount(&ingredient.amount, factor),
+                    ..ingredient
+                }),
+            }
+        }
+        expanded
+    }
+
+    pub fn bake(
+        self,
+        nutrition: &NutritionData,
+        catalogue: &CatalogueData,
+        recipes: &HashMap<String, RawRecipe>,
+    ) -> BakedRecipe {
+        let sections = self.sections(recipes);
+        let ingredients_sections = sections
             .iter()
             .map(|s| IngredientsSection {
                 heading: bake_string(&s.heading),
@@ -65,22 +267,149 @@ impl RawRecipe {
                         name: bake_string(&i.name),
                         amount: bake_string(&i.amount),
                         unit: i.unit.as_deref().map(bake_string),
+                        recipe: i.recipe.as_deref().map(bake_string),
                     })
                     .collect(),
+                recipe: s.recipe.as_deref().map(bake_string),
             })
             .collect::<Vec<_>>();
+        let ingredients: Vec<_> = sections.iter().flat_map(|s| &s.ingredients).collect();
+        let (instructions, steps) =
+            bake_instructions(&self.instructions, &ingredients, 1.0, catalogue);
+        let expanded = self.expanded_ingredients(recipes);
         BakedRecipe {
             name: bake_string(&self.name),
             description: bake_string(&self.description),
-            ingredient_summaries: make_ingredient_summaries(&ingredients_sections),
+            ingredient_summaries: make_ingredient_summaries(&expanded, catalogue),
             ingredients_sections,
-            instructions: bake_md_string(&self.instructions),
+            instructions,
+            steps,
             source: bake_md_string(&self.source),
             categories: self.categories.iter().map(|s| bake_string(s)).collect(),
+            nutrition: nutrition.calculate(&self, catalogue, recipes),
+            prep_time: self.prep_time,
+            cook_time: self.cook_time,
+            rest_time: self.rest_time,
+            total_time: self.total_time(),
         }
     }
 }
 
+#[derive(Debug, Default)]
+pub struct Coverage {
+    pub covered: usize,
+    /// The names of the ingredients that are not on hand.
+    pub missing: Vec<String>,
+}
+
+/// How deep references to other recipes are followed, so cycles in edited files end.
+const MAX_REFERENCE_DEPTH: usize = 4;
+
+/// The longest prep, cook or rest time in minutes, 30 days, enough for long fermentations.
+const MAX_DURATION: u32 = 30 * 24 * 60;
+
+const MAX_SHORT_TEXT: usize = 200;
+const MAX_TEXT: usize = 5_000;
+const MAX_INSTRUCTIONS: usize = 50_000;
+
+/// A problem with a single field of a [`RawRecipe`]. The path names the field like the JSON
+/// representation does, e.g. `IngredientsSections.0.Ingredients.2.Amount`.
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct FieldError {
+    pub path: String,
+    pub code: &'static str,
+}
+
+impl RawRecipe {
+    /// Checks a cleaned recipe.
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let mut check_length = |path: String, value: &str, max: usize| {
+            if value.chars().count() > max {
+                errors.push(FieldError {
+                    path,
+                    code: "too-long",
+                });
+            }
+        };
+        check_length("Name".into(), &self.name, MAX_SHORT_TEXT);
+        check_length("Description".into(), &self.description, MAX_TEXT);
+        check_length("Instructions".into(), &self.instructions, MAX_INSTRUCTIONS);
+        check_length("Source".into(), &self.source, MAX_TEXT);
+        for (i, category) in self.categories.iter().enumerate() {
+            check_length(format!("Categories.{i}"), category, MAX_SHORT_TEXT);
+        }
+        for (i, section) in self.ingredients_sections.iter().enumerate() {
+            let path = format!("IngredientsSections.{i}");
+            check_length(format!("{path}.Heading"), &section.heading, MAX_SHORT_TEXT);
+            for (j, ingredient) in section.ingredients.iter().enumerate() {
+                let path = format!("{path}.Ingredients.{j}");
+                check_length(format!("{path}.Name"), &ingredient.name, MAX_SHORT_TEXT);
+                check_length(format!("{path}.Amount"), &ingredient.amount, MAX_SHORT_TEXT);
+                if let Some(unit) = &ingredient.unit {
+                    check_length(format!("{path}.Unit"), unit, MAX_SHORT_TEXT);
+                }
+            }
+        }
+
+        for (path, time) in [
+            ("PrepTime", self.prep_time),
+            ("CookTime", self.cook_time),
+            ("RestTime", self.rest_time),
+        ] {
+            if time.is_some_and(|t| t > MAX_DURATION) {
+                errors.push(FieldError {
+                    path: path.into(),
+                    code: "invalid-duration",
+                });
+            }
+        }
+        if self.servings == Some(0) {
+            errors.push(FieldError {
+                path: "Servings".into(),
+                code: "invalid-servings",
+            });
+        }
+        if to_id_string(&self.name).is_empty() {
+            errors.push(FieldError {
+                path: "Name".into(),
+                code: "empty-id",
+            });
+        }
+        for (i, section) in self.ingredients_sections.iter().enumerate() {
+            let path = format!("IngredientsSections.{i}");
+            let duplicate = self.ingredients_sections[..i]
+                .iter()
+                .any(|s| s.heading == section.heading);
+            if !section.heading.is_empty() && duplicate {
+                errors.push(FieldError {
+                    path: format!("{path}.Heading"),
+                    code: "duplicate-heading",
+                });
+            }
+            for (j, ingredient) in section.ingredients.iter().enumerate() {
+                let path = format!("{path}.Ingredients.{j}");
+                if ingredient.name.is_empty() {
+                    errors.push(FieldError {
+                        path: format!("{path}.Name"),
+                        code: "empty-name",
+                    });
+                }
+                let valid =
+                    f64::from_str(&ingredient.amount).is_ok_and(|a| a.is_finite() && a >= 0.0);
+                if !ingredient.amount.is_empty() && !valid {
+                    errors.push(FieldError {
+                        path: format!("{path}.Amount"),
+                        code: "invalid-amount",
+                    });
+                }
+            }
+        }
+        errors
+    }
+}
+
 fn clean(s: &mut String) {
     *s = s.trim().to_string();
 }
@@ -102,17 +431,36 @@ pub struct BakedRecipe {
     ingredients_sections: Vec<IngredientsSection>,
     ingredient_summaries: Vec<IngredientSummary>,
     instructions: String,
+    /// The instructions split into steps, with the ingredients each of them uses.
+    steps: Vec<Step>,
     source: String,
     categories: Vec<String>,
+    nutrition: Option<NutritionInfo>,
+    prep_time: Option<u32>,
+    cook_time: Option<u32>,
+    rest_time: Option<u32>,
+    total_time: Option<u32>,
 }
 
-fn bake_md_string(s: &str) -> String {
-    let options = Options {
+pub(crate) fn md_options() -> Options<'static> {
+    Options {
         extension: Extension::default(),
         parse: Parse::default(),
         render: Render::default(),
-    };
-    markdown_to_html(s, &options)
+    }
+}
+
+fn bake_md_string(s: &str) -> String {
+    markdown_to_html(s, &md_options())
+}
+
+/// Multiplies an amount like the recipe page does, rounded to three decimals. Amounts that are
+/// not a number, like `etwas`, are kept.
+pub(crate) fn scale_amount(amount: &str, factor: f64) -> String {
+    match f64::from_str(amount) {
+        Ok(amount) => ((amount * factor * 1000.0).round() / 1000.0).to_string(),
+        Err(_) => amount.to_owned(),
+    }
 }
 
 pub(crate) fn bake_string(s: &str) -> String {
@@ -121,32 +469,377 @@ pub(crate) fn bake_string(s: &str) -> String {
     String::from_utf8(output.into_inner()).unwrap()
 }
 
-fn make_ingredient_summaries(sections: &[IngredientsSection]) -> Vec<IngredientSummary> {
-    let mut ingredients: HashMap<_, IngredientSummary> = HashMap::new();
+/// Adds up the amounts of ingredients with the same canonical name, converting between units
+/// where possible.
+fn make_ingredient_summaries(
+    ingredients: &[Ingredient],
+    catalogue: &CatalogueData,
+) -> Vec<IngredientSummary> {
+    let mut summaries: Vec<IngredientSummary> = Vec::new();
+    let mut canonical_names: Vec<String> = Vec::new();
 
-    for section in sections {
-        for ingredient in &section.ingredients {
-            let Ok(amount) = f64::from_str(&ingredient.amount) else {
-                continue;
-            };
-            let key = (&ingredient.name, &ingredient.unit);
-            let len = ingredients.len();
-            match ingredients.entry(key) {
-                Entry::Occupied(mut e) => {
-                    e.get_mut().amount += amount;
-                }
-                Entry::Vacant(e) => {
-                    e.insert(IngredientSummary {
-                        name: ingredient.name.clone(),
-                        unit: ingredient.unit.clone(),
-                        amount,
-                        recipe_offset: len,
-                    });
-                }
-            }
+    for ingredient in ingredients {
+        let Ok(amount) = f64::from_str(&ingredient.amount) else {
+            continue;
+        };
+        let unit = ingredient.unit.as_deref();
+        let density = catalogue.density(&ingredient.name);
+        let canonical = catalogue.canonical(&ingredient.name);
+        let existing = summaries
+            .iter_mut()
+            .zip(&canonical_names)
+            .filter(|(_, c)| **c == canonical)
+            .find_map(|(s, _)| {
+                let amount = if s.unit.as_deref() == unit {
+                    amount
+                } else {
+                    convert(amount, unit?, s.unit.as_deref()?, density)?
+                };
+                Some((s, amount))
+            });
+        if let Some((summary, amount)) = existing {
+            summary.amount += amount;
+        } else {
+            summaries.push(IngredientSummary {
+                name: ingredient.name.clone(),
+                unit: ingredient.unit.clone(),
+                amount,
+                recipe_offset: summaries.len(),
+            });
+            canonical_names.push(canonical);
         }
     }
-    let mut result: Vec<_> = ingredients.into_values().collect();
-    result.sort_unstable_by_key(|s| s.recipe_offset);
-    result
+    for summary in &mut summaries {
+        summary.name = bake_string(&summary.name);
+        summary.unit = summary.unit.as_deref().map(bake_string);
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use super::{FieldError, Ingredient, IngredientsSection, RawRecipe, make_ingredient_summaries};
+    use crate::catalogue::{CatalogueData, CatalogueEntry};
+
+    fn ingredient(name: &str, amount: &str) -> Ingredient {
+        Ingredient {
+            name: name.into(),
+            amount: amount.into(),
+            unit: None,
+            recipe: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut recipe = RawRecipe {
+            name: "Kuchen".into(),
+            ingredients_sections: vec![
+                IngredientsSection {
+                    heading: "Teig".into(),
+                    ingredients: vec![ingredient("Mehl", "2,5"), ingredient("Salz", "")],
+                    ..IngredientsSection::default()
+                },
+                IngredientsSection {
+                    heading: "Teig".into(),
+                    ingredients: vec![
+                        ingredient("", "1"),
+                        ingredient("Zucker", "viel"),
+                        ingredient("Milch", "NaN"),
+                        ingredient("Milch", "inf"),
+                        ingredient("Milch", "-3"),
+                        ingredient("Milch", "1e400"),
+                        ingredient("Milch", "0"),
+                    ],
+                    ..IngredientsSection::default()
+                },
+            ],
+            categories: vec!["x".repeat(201)],
+            ..RawRecipe::default()
+        };
+        recipe.clean(&CatalogueData::default());
+        let error = |path: &str, code| FieldError {
+            path: path.into(),
+            code,
+        };
+        assert_eq!(
+            recipe.validate(),
+            [
+                error("Categories.0", "too-long"),
+                error("IngredientsSections.1.Heading", "duplicate-heading"),
+                error("IngredientsSections.1.Ingredients.0.Name", "empty-name"),
+                error(
+                    "IngredientsSections.1.Ingredients.1.Amount",
+                    "invalid-amount"
+                ),
+                error(
+                    "IngredientsSections.1.Ingredients.2.Amount",
+                    "invalid-amount"
+                ),
+                error(
+                    "IngredientsSections.1.Ingredients.3.Amount",
+                    "invalid-amount"
+                ),
+                error(
+                    "IngredientsSections.1.Ingredients.4.Amount",
+                    "invalid-amount"
+                ),
+                error(
+                    "IngredientsSections.1.Ingredients.5.Amount",
+                    "invalid-amount"
+                ),
+            ]
+        );
+
+        recipe.name = "!".into();
+        assert!(recipe.validate().contains(&error("Name", "empty-id")));
+
+        recipe.prep_time = Some(u32::MAX);
+        recipe.cook_time = Some(1);
+        recipe.rest_time = Some(30 * 24 * 60);
+        let errors = recipe.validate();
+        assert!(errors.contains(&error("PrepTime", "invalid-duration")));
+        assert!(!errors.contains(&error("CookTime", "invalid-duration")));
+        assert!(!errors.contains(&error("RestTime", "invalid-duration")));
+        assert_eq!(recipe.total_time(), Some(u32::MAX));
+    }
+
+    #[test]
+    fn test_clean_and_matches() {
+        let catalogue = CatalogueData::new(BTreeMap::from([(
+            "Zwiebel".into(),
+            CatalogueEntry {
+                plural: Some("Zwiebeln".into()),
+                synonyms: vec!["Gemüsezwiebel".into()],
+                density: None,
+                staple: false,
+            },
+        )]));
+        let mut recipe = RawRecipe {
+            name: "Zwiebelkuchen".into(),
+            ingredients_sections: vec![IngredientsSection {
+                ingredients: vec![
+                    ingredient(" zwiebeln", "3"),
+                    ingredient("gemüsezwiebel", "1"),
+                ],
+                ..IngredientsSection::default()
+            }],
+            nutrition_foods: BTreeMap::from([(" ZWIEBELN".into(), "Zwiebel, roh".into())]),
+            ..RawRecipe::default()
+        };
+        recipe.clean(&catalogue);
+        let names: Vec<_> = recipe.ingredients_sections[0]
+            .ingredients
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect();
+        assert_eq!(names, ["Zwiebeln", "Zwiebel"]);
+        assert_eq!(
+            recipe.nutrition_foods.keys().collect::<Vec<_>>(),
+            ["Zwiebeln"]
+        );
+
+        recipe.name = "Flammkuchen".into();
+        let recipes = HashMap::new();
+        assert!(recipe.matches("GEMÜSEZWIEBEL", &catalogue, &recipes));
+        assert!(recipe.matches("kuchen", &catalogue, &recipes));
+        assert!(recipe.matches("wiebel", &catalogue, &recipes));
+        assert!(!recipe.matches("Speck", &catalogue, &recipes));
+
+        // Ingredients of referenced recipes match too.
+        recipe.ingredients_sections[0].ingredients.push(Ingredient {
+            recipe: Some("teig".into()),
+            ..ingredient("Teig", "1")
+        });
+        let teig = RawRecipe {
+            name: "Flammkuchenteig".into(),
+            ingredients_sections: vec![IngredientsSection {
+                ingredients: vec![ingredient("Speck", "100")],
+                ..IngredientsSection::default()
+            }],
+            ..recipe.clone()
+        };
+        assert!(!recipe.matches("Speck", &catalogue, &recipes));
+        let recipes = HashMap::from([("teig".into(), teig)]);
+        assert!(recipe.matches("Speck", &catalogue, &recipes));
+        assert!(recipe.matches("teig", &catalogue, &recipes));
+    }
+
+    #[test]
+    fn test_coverage() {
+        let catalogue = CatalogueData::new(BTreeMap::from([
+            (
+                "Zwiebel".into(),
+                CatalogueEntry {
+                    plural: Some("Zwiebeln".into()),
+                    ..CatalogueEntry::default()
+                },
+            ),
+            (
+                "Salz".into(),
+                CatalogueEntry {
+                    staple: true,
+                    ..CatalogueEntry::default()
+                },
+            ),
+        ]));
+        let section = |names: &[&str]| IngredientsSection {
+            ingredients: names.iter().map(|n| ingredient(n, "")).collect(),
+            ..IngredientsSection::default()
+        };
+        let teig = RawRecipe {
+            name: "Hefeteig".into(),
+            ingredients_sections: vec![section(&["Mehl", "Salz", "Hefe"])],
+            ..RawRecipe::default()
+        };
+        let recipe = RawRecipe {
+            name: "Zwiebelkuchen".into(),
+            ingredients_sections: vec![IngredientsSection {
+                recipe: Some("teig".into()),
+                ..section(&["Zwiebeln", "Speck", "Salz"])
+            }],
+            ..teig.clone()
+        };
+        let recipes = HashMap::from([("teig".into(), teig)]);
+        let on_hand = HashSet::from(["mehl".to_owned(), "zwiebel".to_owned()]);
+        let coverage = recipe.coverage(&on_hand, &catalogue, &recipes, false);
+        assert_eq!(coverage.covered, 2);
+        assert_eq!(coverage.missing, ["Salz", "Hefe", "Speck"]);
+        let coverage = recipe.coverage(&on_hand, &catalogue, &recipes, true);
+        assert_eq!(coverage.missing, ["Hefe", "Speck"]);
+    }
+
+    #[test]
+    fn test_summaries() {
+        let with_unit = |name, amount, unit: &str| Ingredient {
+            unit: Some(unit.into()),
+            ..ingredient(name, amount)
+        };
+        let ingredients = [
+            with_unit("Mehl", "150", "g"),
+            with_unit("Zucker", "1", "EL"),
+            with_unit("Mehl", "200", "ml"),
+            with_unit("Zucker", "100", "g"),
+            with_unit("Mehl", "0.1", "kg"),
+            ingredient("Zwiebel", "1"),
+            ingredient("Gemüsezwiebeln", "2"),
+        ];
+        let catalogue = CatalogueData::new(BTreeMap::from([
+            (
+                "Mehl".into(),
+                CatalogueEntry {
+                    density: Some(0.5),
+                    ..CatalogueEntry::default()
+                },
+            ),
+            (
+                "Zwiebel".into(),
+                CatalogueEntry {
+                    plural: Some("Zwiebeln".into()),
+                    synonyms: vec!["Gemüsezwiebeln".into()],
+                    density: None,
+                    staple: false,
+                },
+            ),
+        ]));
+        let summaries: Vec<_> = make_ingredient_summaries(&ingredients, &catalogue)
+            .into_iter()
+            .map(|s| (s.name, s.unit, s.amount.to_string()))
+            .collect();
+        assert_eq!(
+            summaries,
+            [
+                ("Mehl".into(), Some("g".into()), "350".into()),
+                ("Zucker".into(), Some("EL".into()), "1".into()),
+                ("Zucker".into(), Some("g".into()), "100".into()),
+                ("Zwiebel".into(), None, "3".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        let recipe = |name: &str, sections: Vec<IngredientsSection>| RawRecipe {
+            name: name.into(),
+            ingredients_sections: sections,
+            ..RawRecipe::default()
+        };
+        let section = |recipe: Option<&str>, ingredients: Vec<Ingredient>| IngredientsSection {
+            ingredients,
+            recipe: recipe.map(Into::into),
+            ..IngredientsSection::default()
+        };
+        let reference = |name: &str, amount: &str, unit: Option<&str>, id: &str| Ingredient {
+            unit: unit.map(Into::into),
+            recipe: Some(id.into()),
+            ..ingredient(name, amount)
+        };
+        let recipes = HashMap::from([
+            (
+                "teig".into(),
+                recipe(
+                    "Mürbeteig",
+                    vec![section(
+                        None,
+                        vec![ingredient("Mehl", "200"), ingredient("Butter", "100")],
+                    )],
+                ),
+            ),
+            (
+                "sosse".into(),
+                recipe(
+                    "Soße",
+                    vec![section(
+                        None,
+                        vec![ingredient("Sahne", "100"), ingredient("Salz", "etwas")],
+                    )],
+                ),
+            ),
+        ]);
+        let tarte = recipe(
+            "Tarte",
+            vec![
+                section(Some("teig"), vec![ingredient("Ei", "1")]),
+                section(
+                    None,
+                    vec![
+                        reference("Soße", "2", None, "sosse"),
+                        reference("Mürbeteig", "1", Some("Portion"), "teig"),
+                        reference("Gelöscht", "1", None, "unknown"),
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(
+            tarte.references().collect::<Vec<_>>(),
+            ["teig", "sosse", "teig", "unknown"]
+        );
+
+        let sections = tarte.sections(&recipes);
+        let names: Vec<_> = sections[0].ingredients.iter().map(|i| &i.name).collect();
+        assert_eq!(names, ["Mehl", "Butter", "Ei"]);
+        assert_eq!(sections[1].ingredients.len(), 3);
+
+        let expanded: Vec<_> = tarte
+            .expanded_ingredients(&recipes)
+            .into_iter()
+            .map(|i| (i.name, i.amount))
+            .collect();
+        let expected = [
+            ("Mehl", "200"),
+            ("Butter", "100"),
+            ("Ei", "1"),
+            ("Sahne", "200"),
+            ("Salz", "etwas"),
+            ("Mehl", "200"),
+            ("Butter", "100"),
+            ("Gelöscht", "1"),
+        ];
+        assert_eq!(
+            expanded,
+            expected.map(|(n, a)| (n.to_string(), a.to_string()))
+        );
+    }
 }
@@ -1,20 +1,45 @@
-use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::path::{Path, PathBuf};
 
 use tokio::fs::{read_dir, read_to_string};
 use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
-use tracing::{error, warn};
+use tracing::error;
 
 use crate::error::Error;
-use crate::id::to_id_string;
-use crate::recipe::RawRecipe;
+use crate::id::{generate_id, to_id_string};
+use crate::metrics::METRICS;
+use crate::recipe::{FieldError, RawRecipe};
 
 pub fn handle_io_error(path: &Path, e: &std::io::Error) -> Error {
     error!("Failed to write {path:?}: {e}");
+    METRICS.record_storage_write_failure();
     Error::Internal
 }
 
+pub fn read_error(path: &Path, e: impl Display) -> std::io::Error {
+    std::io::Error::other(format!("Failed to read {}: {e}", path.display()))
+}
+
+pub enum Issue {
+    EmptyId(PathBuf),
+    Duplicate(PathBuf, PathBuf),
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::EmptyId(path) => {
+                write!(f, "{}: name results in an empty slug", path.display())
+            }
+            Issue::Duplicate(path, other) => {
+                write!(f, "{}: same slug as {}", path.display(), other.display())
+            }
+        }
+    }
+}
+
 struct RecipesIo(PathBuf);
 
 struct Write {
@@ -28,9 +53,21 @@ struct Delete {
 
 impl RecipesIo {
     async fn read(path: &Path) -> std::io::Result<RawRecipe> {
-        let text = read_to_string(path).await?;
-        let recipe = serde_json::from_str(&text).unwrap();
-        Ok(recipe)
+        let text = read_to_string(path)
+            .await
+            .map_err(|e| read_error(path, e))?;
+        serde_json::from_str(&text).map_err(|e| read_error(path, e))
+    }
+
+    async fn read_dir(path: &Path) -> std::io::Result<Vec<(PathBuf, RawRecipe)>> {
+        let mut t = read_dir(path).await.map_err(|e| read_error(path, e))?;
+        let mut recipes = Vec::new();
+        while let Some(t) = t.next_entry().await.map_err(|e| read_error(path, e))? {
+            let path = t.path();
+            let recipe = RecipesIo::read(&path).await?;
+            recipes.push((path, recipe));
+        }
+        Ok(recipes)
     }
 
     fn path_of(&self, id: &str) -> PathBuf {
@@ -63,101 +100,300 @@ impl RecipesIo {
     }
 }
 
+#[derive(Default)]
+struct RecipesData {
+    recipes: HashMap<String, RawRecipe>,
+    /// Slugs and aliases with the ids of their recipes, rebuilt on every change.
+    keys: HashMap<String, String>,
+}
+
+impl RecipesData {
+    fn new(recipes: HashMap<String, RawRecipe>) -> RecipesData {
+        let mut data = RecipesData {
+            recipes,
+            keys: HashMap::new(),
+        };
+        data.reindex();
+        data
+    }
+
+    /// Slugs win over aliases, in case old files have the same key for both.
+    fn reindex(&mut self) {
+        self.keys = self
+            .recipes
+            .iter()
+            .map(|(id, r)| (to_id_string(&r.name), id.clone()))
+            .collect();
+        for (id, recipe) in &self.recipes {
+            for alias in &recipe.aliases {
+                self.keys.entry(alias.clone()).or_insert_with(|| id.clone());
+            }
+        }
+    }
+
+    /// Finds a recipe by its id, its slug or an old slug, in that order.
+    fn find(&self, key: &str) -> Option<(&String, &RawRecipe)> {
+        self.recipes.get_key_value(key).or_else(|| {
+            let id = self.keys.get(key)?;
+            self.recipes.get_key_value(id)
+        })
+    }
+
+    /// Whether the slug would lead to a recipe other than `id`, by another recipe's slug, alias
+    /// or id.
+    fn slug_taken(&self, slug: &str, id: &str) -> bool {
+        self.keys.get(slug).is_some_and(|other| other != id)
+            || (slug != id && self.recipes.contains_key(slug))
+    }
+}
+
+/// Recipes keyed by their permanent id, which is also the file name. The URLs use the slug of the
+/// name instead, see [`Recipes::resolve`].
 pub struct Recipes {
-    recipes: RwLock<HashMap<String, RawRecipe>>,
+    data: RwLock<RecipesData>,
     io: Mutex<RecipesIo>,
 }
 
 impl Recipes {
-    pub async fn load_dir(path: &Path) -> Recipes {
-        let mut t = read_dir(path)
-            .await
-            .unwrap_or_else(|e| panic!("Failed to list recipes dir: {e}"));
+    pub async fn load_dir(path: &Path) -> std::io::Result<Recipes> {
         let mut recipes = HashMap::new();
-        while let Some(t) = t
-            .next_entry()
-            .await
-            .unwrap_or_else(|e| panic!("Failed to list recipes dir: {e}"))
-        {
-            let path = t.path();
-            let recipe = RecipesIo::read(&path)
-                .await
-                .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
-            let id = to_id_string(&recipe.name);
-            if id != path.file_stem().unwrap().to_str().unwrap() {
-                let file_name = path.file_name().unwrap().to_str().unwrap();
-                warn!(
-                    "Recipe {:?} has a name {:?} that does not fit its id {id:?}",
-                    recipe.name, file_name
-                );
-            }
+        for (path, recipe) in RecipesIo::read_dir(path).await? {
+            let id = path.file_stem().unwrap().to_str().unwrap().to_owned();
             recipes.insert(id, recipe);
         }
-        Recipes {
-            recipes: RwLock::new(recipes),
+        Ok(Recipes {
+            data: RwLock::new(RecipesData::new(recipes)),
             io: Mutex::new(RecipesIo(path.to_path_buf())),
+        })
+    }
+
+    /// Finds recipe files that would not be loaded as expected.
+    pub async fn check_dir(path: &Path) -> std::io::Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        let mut slugs = HashMap::new();
+        for (path, recipe) in RecipesIo::read_dir(path).await? {
+            let slug = to_id_string(&recipe.name);
+            if slug.is_empty() {
+                issues.push(Issue::EmptyId(path));
+                continue;
+            }
+            if let Some(other) = slugs.insert(slug, path.clone()) {
+                issues.push(Issue::Duplicate(path, other));
+            }
         }
+        Ok(issues)
+    }
+
+    /// Moves the recipe files, named after slugs from before ids were permanent, to generated
+    /// ids. The old id is kept as an alias, so old links keep working, and references to the
+    /// recipes are updated. Returns the moved files.
+    ///
+    /// The new ids are chosen for all files at once and recorded in `<directory>.ids.json` before
+    /// any file is touched. Files are written under their new id before the old ones are removed,
+    /// so an interrupted run can be repeated with the same ids. Once the record exists, only the
+    /// files it lists are moved.
+    pub async fn reindex_dir(path: &Path) -> std::io::Result<Vec<(PathBuf, PathBuf)>> {
+        let io = RecipesIo(path.to_path_buf());
+        let recipes = RecipesIo::read_dir(path).await?;
+        let stem = |path: &Path| path.file_stem().unwrap().to_string_lossy().into_owned();
+        let record = path.with_extension("ids.json");
+        let ids: HashMap<String, String> = match read_to_string(&record).await {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| read_error(&record, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let ids = recipes
+                    .iter()
+                    .map(|(path, _)| (stem(path), generate_id()))
+                    .collect();
+                let temporary = record.with_extension("tmp");
+                tokio::fs::write(&temporary, serde_json::to_string(&ids).unwrap()).await?;
+                tokio::fs::rename(&temporary, &record).await?;
+                ids
+            }
+            Err(e) => return Err(read_error(&record, e)),
+        };
+
+        let mut moved = Vec::new();
+        for (old_path, mut recipe) in recipes {
+            let old_id = stem(&old_path);
+            let mut changed = false;
+            for (_, reference) in recipe.references_mut() {
+                if let Some(id) = reference.as_ref().and_then(|r| ids.get(r)) {
+                    *reference = Some(id.clone());
+                    changed = true;
+                }
+            }
+            let Some(new_id) = ids.get(&old_id) else {
+                if changed {
+                    let write = io.prepare_write(&old_id, &recipe);
+                    tokio::fs::write(&write.path, &write.content).await?;
+                }
+                continue;
+            };
+            if to_id_string(&recipe.name) != old_id && !recipe.aliases.contains(&old_id) {
+                recipe.aliases.push(old_id);
+            }
+            let write = io.prepare_write(new_id, &recipe);
+            tokio::fs::write(&write.path, &write.content).await?;
+            moved.push((old_path, write.path));
+        }
+        for (old_path, _) in &moved {
+            tokio::fs::remove_file(old_path).await?;
+        }
+        Ok(moved)
+    }
+
+    /// Whether the recipes directory is still accessible.
+    pub async fn is_available(&self) -> bool {
+        let path = self.io.lock().await.0.clone();
+        tokio::fs::metadata(path).await.is_ok_and(|m| m.is_dir())
     }
 
     pub async fn list(&self) -> RwLockReadGuard<'_, HashMap<String, RawRecipe>> {
-        self.recipes.read().await
+        RwLockReadGuard::map(self.data.read().await, |d| &d.recipes)
     }
 
     pub async fn get(&self, id: &str) -> Result<RawRecipe, Error> {
-        let recipes = self.recipes.read().await;
-        recipes.get(id).cloned().ok_or(Error::NotFound)
+        let data = self.data.read().await;
+        data.recipes.get(id).cloned().ok_or(Error::NotFound)
+    }
+
+    /// Finds a recipe by its id, its slug or an old slug, in that order. Returns the id and the
+    /// current slug.
+    pub async fn resolve(&self, key: &str) -> Result<(String, String), Error> {
+        let data = self.data.read().await;
+        data.find(key)
+            .map(|(id, r)| (id.clone(), to_id_string(&r.name)))
+            .ok_or(Error::NotFound)
+    }
+
+    /// Replaces the keys of referenced recipes, like their slugs, with their ids. Reports unknown
+    /// recipes and references that would lead back to the recipe `id`.
+    pub async fn link(&self, id: Option<&str>, recipe: &mut RawRecipe) -> Vec<FieldError> {
+        let data = self.data.read().await;
+        let mut errors = Vec::new();
+        for (path, reference) in recipe.references_mut() {
+            let Some(key) = reference.as_deref() else {
+                continue;
+            };
+            let Some((other, _)) = data.find(&key.to_lowercase()) else {
+                errors.push(FieldError {
+                    path,
+                    code: "unknown-recipe",
+                });
+                continue;
+            };
+            if id.is_some_and(|id| leads_to(&data.recipes, other, id)) {
+                errors.push(FieldError {
+                    path,
+                    code: "recipe-cycle",
+                });
+                continue;
+            }
+            *reference = Some(other.clone());
+        }
+        errors
     }
 
     pub async fn create(&self, id: String, recipe: RawRecipe) -> Result<(), Error> {
         let mut io = self.io.lock().await;
         let write = io.prepare_write(&id, &recipe);
-        let mut recipes = self.recipes.write().await;
-        match recipes.entry(id) {
+        let mut data = self.data.write().await;
+        if data.slug_taken(&to_id_string(&recipe.name), &id) || data.keys.contains_key(&id) {
+            return Err(Error::AlreadyExists);
+        }
+        match data.recipes.entry(id) {
             Entry::Occupied(_) => {
                 return Err(Error::AlreadyExists);
             }
             Entry::Vacant(e) => {
                 e.insert(recipe);
-                drop(recipes);
+                data.reindex();
+                drop(data);
                 io.write(&write).await?;
             }
         }
         Ok(())
     }
 
+    /// Recipes referencing the deleted one keep its ingredients, including those of the recipes
+    /// it references: sections get them as their own and ingredients become plain ones.
     pub async fn delete(&self, id: &str) -> Result<(), Error> {
         let mut io = self.io.lock().await;
         let delete = io.prepare_delete(id);
-        let mut recipes = self.recipes.write().await;
-        recipes.remove(id).ok_or(Error::NotFound)?;
-        io.delete(&delete).await
-    }
-
-    pub async fn replace(&self, id: &str, new_id: String, recipe: RawRecipe) -> Result<(), Error> {
-        let mut io = self.io.lock().await;
-        let write = io.prepare_write(&new_id, &recipe);
-        let delete = io.prepare_delete(id);
-        let mut recipes = self.recipes.write().await;
-        match recipes.entry(new_id) {
-            Entry::Occupied(mut e) => {
-                if id == e.key() {
-                    e.insert(recipe);
-                    drop(recipes);
-                    io.write(&write).await?;
-                } else {
-                    return Err(Error::AlreadyExists);
-                }
+        let mut data = self.data.write().await;
+        let deleted = data.recipes.get(id).ok_or(Error::NotFound)?;
+        let deleted_ingredients: Vec<_> = deleted
+            .sections(&data.recipes)
+            .into_iter()
+            .flat_map(|s| s.ingredients)
+            .collect();
+        data.recipes.remove(id);
+        let mut writes = Vec::new();
+        for (other_id, other) in &mut data.recipes {
+            if !other.references().any(|r| r == id) {
+                continue;
             }
-            Entry::Vacant(e) => {
-                e.insert(recipe);
-                recipes.remove(id);
-                drop(recipes);
-                io.write(&write).await?;
-                io.delete(&delete).await?;
+            for section in &mut other.ingredients_sections {
+                if section.recipe.as_deref() == Some(id) {
+                    let mut ingredients = deleted_ingredients.clone();
+                    ingredients.append(&mut section.ingredients);
+                    section.ingredients = ingredients;
+                    section.recipe = None;
+                }
+                for ingredient in &mut section.ingredients {
+                    if ingredient.recipe.as_deref() == Some(id) {
+                        ingredient.recipe = None;
+                    }
+                }
             }
+            writes.push(io.prepare_write(other_id, other));
+        }
+        data.reindex();
+        drop(data);
+        io.delete(&delete).await?;
+        for write in &writes {
+            io.write(write).await?;
         }
         Ok(())
     }
+
+    /// Keeps the id. If the slug changes, the old one becomes an alias.
+    pub async fn replace(&self, id: &str, mut recipe: RawRecipe) -> Result<(), Error> {
+        let mut io = self.io.lock().await;
+        let mut data = self.data.write().await;
+        let slug = to_id_string(&recipe.name);
+        if data.slug_taken(&slug, id) {
+            return Err(Error::AlreadyExists);
+        }
+        let current = data.recipes.get_mut(id).ok_or(Error::NotFound)?;
+        let mut aliases = std::mem::take(&mut current.aliases);
+        aliases.extend(recipe.aliases);
+        aliases.push(to_id_string(&current.name));
+        let mut seen = HashSet::new();
+        aliases.retain(|a| *a != slug && a != id && seen.insert(a.clone()));
+        recipe.aliases = aliases;
+
+        let write = io.prepare_write(id, &recipe);
+        *current = recipe;
+        data.reindex();
+        drop(data);
+        io.write(&write).await
+    }
+}
+
+/// Whether the recipe `from` is `to` or references it, directly or through other recipes.
+fn leads_to(recipes: &HashMap<String, RawRecipe>, from: &str, to: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut pending = vec![from];
+    while let Some(id) = pending.pop() {
+        if id == to {
+            return true;
+        }
+        if seen.insert(id) {
+            pending.extend(recipes.get(id).into_iter().flat_map(RawRecipe::references));
+        }
+    }
+    false
 }
 
 #[cfg(test)]
@@ -166,6 +402,7 @@ mod tests {
 
     use tempfile::TempDir;
 
+    use crate::error::Error;
     use crate::recipe::{Ingredient, IngredientsSection, RawRecipe};
     use crate::recipes::Recipes;
 
@@ -173,7 +410,7 @@ mod tests {
     async fn test_read_write() {
         let dir = TempDir::new().unwrap();
         let path = dir.path();
-        let recipes = Recipes::load_dir(path).await;
+        let recipes = Recipes::load_dir(path).await.unwrap();
 
         assert_eq!(&*recipes.list().await, &HashMap::new());
 
@@ -186,11 +423,13 @@ mod tests {
                     name: "g".to_string(),
                     amount: "h".to_string(),
                     unit: Some("i".to_string()),
+                    recipe: None,
                 }],
+                ..IngredientsSection::default()
             }],
             instructions: "d".to_string(),
             source: "e".to_string(),
-            categories: vec![],
+            ..RawRecipe::default()
         };
         recipes
             .create("test-1".to_string(), recipe.clone())
@@ -205,10 +444,13 @@ mod tests {
                 .collect::<Vec<_>>(),
             &[("test-1", "test 1")]
         );
-        assert_eq!(recipes.recipes.read().await.get("test-1"), Some(&recipe));
+        assert_eq!(
+            recipes.data.read().await.recipes.get("test-1"),
+            Some(&recipe)
+        );
 
         {
-            let recipes = Recipes::load_dir(path).await;
+            let recipes = Recipes::load_dir(path).await.unwrap();
             assert_eq!(
                 &recipes
                     .list()
@@ -220,11 +462,123 @@ mod tests {
             );
         }
 
+        let mut renamed = recipe.clone();
+        renamed.name = "Test 2".to_string();
+        recipes.replace("test-1", renamed.clone()).await.unwrap();
+        renamed.name = "Test 3".to_string();
+        recipes.replace("test-1", renamed).await.unwrap();
+        let resolved = ("test-1".to_string(), "test-3".to_string());
+        for key in ["test-3", "test-2", "test-1"] {
+            assert_eq!(recipes.resolve(key).await.unwrap(), resolved);
+        }
+        assert_eq!(recipes.get("test-1").await.unwrap().aliases, ["test-2"]);
+        // The id and old slugs keep leading to the recipe.
+        for name in ["test 1", "Test 2", "Test 3"] {
+            let other = RawRecipe {
+                name: name.to_string(),
+                ..recipe.clone()
+            };
+            assert!(matches!(
+                recipes.create("other".to_string(), other).await,
+                Err(Error::AlreadyExists)
+            ));
+        }
+        let other = RawRecipe {
+            name: "Other".to_string(),
+            ..recipe.clone()
+        };
+        assert!(matches!(
+            recipes.create("test-2".to_string(), other.clone()).await,
+            Err(Error::AlreadyExists)
+        ));
+        recipes
+            .create("other".to_string(), other.clone())
+            .await
+            .unwrap();
+        let renamed = RawRecipe {
+            name: "Test 2".to_string(),
+            ..other
+        };
+        assert!(matches!(
+            recipes.replace("other", renamed).await,
+            Err(Error::AlreadyExists)
+        ));
+        assert_eq!(recipes.resolve("test-1").await.unwrap(), resolved);
+        recipes.delete("other").await.unwrap();
+
         recipes.delete("test-1").await.unwrap();
 
         {
-            let recipes = Recipes::load_dir(path).await;
+            let recipes = Recipes::load_dir(path).await.unwrap();
             assert_eq!(&*recipes.list().await, &HashMap::new());
         }
     }
+
+    #[tokio::test]
+    async fn test_references() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+        let recipes = Recipes::load_dir(path).await.unwrap();
+        let ingredient = |name: &str, recipe: Option<&str>| Ingredient {
+            name: name.into(),
+            amount: "1".into(),
+            unit: None,
+            recipe: recipe.map(Into::into),
+        };
+        let recipe = |name: &str, section: Option<&str>, ingredients| RawRecipe {
+            name: name.into(),
+            ingredients_sections: vec![IngredientsSection {
+                ingredients,
+                recipe: section.map(Into::into),
+                ..IngredientsSection::default()
+            }],
+            ..RawRecipe::default()
+        };
+        let teig = recipe("Teig", None, vec![ingredient("Mehl", None)]);
+        recipes.create("t1".into(), teig.clone()).await.unwrap();
+
+        let mut tarte = recipe(
+            "Tarte",
+            Some("Teig"),
+            vec![ingredient("Ei", None), ingredient("Soße", Some("sosse"))],
+        );
+        let errors = recipes.link(None, &mut tarte).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "IngredientsSections.0.Ingredients.1.Recipe");
+        assert_eq!(errors[0].code, "unknown-recipe");
+        assert_eq!(tarte.ingredients_sections[0].recipe.as_deref(), Some("t1"));
+        tarte.ingredients_sections[0].ingredients[1].recipe = Some("t1".into());
+        recipes.create("t2".into(), tarte).await.unwrap();
+
+        let mut cycle = recipe("Teig", None, vec![ingredient("Tarte", Some("tarte"))]);
+        let errors = recipes.link(Some("t1"), &mut cycle).await;
+        assert_eq!(errors[0].code, "recipe-cycle");
+        let mut own = teig.clone();
+        own.ingredients_sections[0].recipe = Some("teig".into());
+        assert_eq!(
+            recipes.link(Some("t1"), &mut own).await[0].code,
+            "recipe-cycle"
+        );
+
+        // Two levels: the quiche gets the tarte's ingredients and, through it, the Teig's.
+        let quiche = recipe("Quiche", Some("t2"), vec![ingredient("Speck", None)]);
+        recipes.create("t3".into(), quiche).await.unwrap();
+        let names = |recipe: &RawRecipe| -> Vec<String> {
+            recipe.ingredients_sections[0]
+                .ingredients
+                .iter()
+                .map(|i| i.name.clone())
+                .collect()
+        };
+        recipes.delete("t2").await.unwrap();
+        let quiche = recipes.get("t3").await.unwrap();
+        assert_eq!(names(&quiche), ["Mehl", "Ei", "Soße", "Speck"]);
+        assert_eq!(quiche.references().collect::<Vec<_>>(), ["t1"]);
+
+        recipes.delete("t1").await.unwrap();
+        let recipes = Recipes::load_dir(path).await.unwrap();
+        let quiche = recipes.get("t3").await.unwrap();
+        assert_eq!(quiche.references().count(), 0);
+        assert_eq!(names(&quiche), ["Mehl", "Ei", "Soße", "Speck"]);
+    }
 }
@@ -1,67 +1,219 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
 
-use actix_web::HttpRequest;
-use actix_web::web::{Data, Form, Html, Json, Path, Redirect, ServiceConfig};
-use serde::Deserialize;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::web::{Data, Form, Html, Json, Path, Query, Redirect, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::{Value, json};
 use tera::context;
 use tracing::instrument;
 
-use crate::auth::{Authenticated, NoPermission, WritePermission};
+use crate::auth::{
+    AdminPermission, Authenticated, NoPermission, ReadPermission, Role, WritePermission,
+};
+use crate::catalogue::CatalogueEntry;
 use crate::context::Context;
+use crate::csrf::CsrfToken;
 use crate::error::Error;
-use crate::id::to_id_string;
-use crate::recipe::{RawRecipe, bake_string};
+use crate::i18n::Language;
+use crate::id::{generate_id, to_id_string};
+use crate::metrics::METRICS;
+use crate::oidc::{PENDING_DURATION, STATE_COOKIE};
+use crate::pantry::PantryItem;
+use crate::recipe::{Ingredient, RawRecipe, bake_string, scale_amount};
+use crate::steps::bake_instructions;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SortOrder {
+    #[default]
+    Name,
+    /// Quickest first, recipes without times last.
+    TotalTime,
+}
+
+#[derive(Default, Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+    /// In minutes. Recipes without times are left out.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    max_time: Option<u32>,
+    #[serde(default)]
+    sort: SortOrder,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct RecipeListEntry {
+    id: String,
+    slug: String,
+    name: String,
+    categories: Vec<String>,
+    prep_time: Option<u32>,
+    cook_time: Option<u32>,
+    rest_time: Option<u32>,
+    total_time: Option<u32>,
+}
+
+/// The recipes matching a search, in the requested order.
+async fn search_recipes(ctx: &Context, query: &SearchQuery) -> Vec<RecipeListEntry> {
+    let q = query.q.as_deref().filter(|q| !q.trim().is_empty());
+    let catalogue = ctx.catalogue.data().await;
+    let all = ctx.recipes.list().await;
+    let mut recipes: Vec<_> = all
+        .iter()
+        .filter(|(_, r)| q.is_none_or(|q| r.matches(q, &catalogue, &all)))
+        .filter(|(_, r)| {
+            query
+                .max_time
+                .is_none_or(|max| r.total_time().is_some_and(|t| t <= max))
+        })
+        .map(|(id, r)| RecipeListEntry {
+            id: id.clone(),
+            slug: to_id_string(&r.name),
+            name: r.name.clone(),
+            categories: r.categories.clone(),
+            prep_time: r.prep_time,
+            cook_time: r.cook_time,
+            rest_time: r.rest_time,
+            total_time: r.total_time(),
+        })
+        .collect();
+    recipes.sort_by_cached_key(|r| r.name.to_lowercase());
+    if query.sort == SortOrder::TotalTime {
+        recipes.sort_by_key(|r| r.total_time.unwrap_or(u32::MAX));
+    }
+    recipes
+}
 
 #[actix_web::get("/")]
-async fn page_home(ctx: Data<Context>, _: Authenticated<NoPermission>) -> Html {
-    let context = {
-        let recipes: HashMap<String, Value> = ctx
-            .recipes.list().await.iter()
-            .map(|(k, v)| (k.clone(), json!({
-                "name": v.name.clone(),
-                "categories": v.categories.iter().cloned().map(Value::String).collect::<Vec<_>>(),
-            })))
-            .collect();
-        context!(
-            base_url => "",
-            recipes => &recipes
-        )
-    };
+async fn page_home(
+    ctx: Data<Context>,
+    _: Authenticated<NoPermission>,
+    language: Language,
+    Query(query): Query<SearchQuery>,
+) -> Result<Html, Error> {
+    let recipes: Vec<_> = search_recipes(&ctx, &query)
+        .await
+        .into_iter()
+        .map(|r| {
+            json!({
+                "name": bake_string(&r.name),
+                "slug": r.slug,
+                "categories": r.categories.iter().map(|c| bake_string(c)).collect::<Vec<_>>(),
+                "total_time": r.total_time,
+            })
+        })
+        .collect();
+    let context = context!(
+        base_url => "",
+        recipes => &recipes,
+        q => &query.q.as_deref().map(bake_string).unwrap_or_default(),
+        max_time => &query.max_time,
+        sort => if query.sort == SortOrder::TotalTime { &"total-time" } else { &"name" },
+        searched => &(query.q.is_some() || query.max_time.is_some()),
+    );
+
+    let rendered = ctx
+        .templates
+        .read()
+        .await
+        .render("home.html", language, &context)?;
+    Ok(Html::new(rendered))
+}
+
+/// Lists the recipes like the home page, taking the same parameters.
+#[actix_web::get("/api/recipes")]
+async fn api_recipes(
+    ctx: Data<Context>,
+    _: Authenticated<NoPermission>,
+    Query(query): Query<SearchQuery>,
+) -> Json<Vec<RecipeListEntry>> {
+    Json(search_recipes(&ctx, &query).await)
+}
 
-    let rendered = ctx.templates.read().await.render("home.html", &context);
-    Html::new(rendered)
+#[derive(Deserialize)]
+struct LoginQuery {
+    invite: Option<String>,
 }
 
 #[actix_web::get("/login")]
 async fn page_login(
     ctx: Data<Context>,
+    language: Language,
     Authenticated(NoPermission(user)): Authenticated<NoPermission>,
-) -> Html {
+    Query(LoginQuery { invite }): Query<LoginQuery>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Result<Html, Error> {
+    let (admin, tokens, preferred_language) = match &user {
+        Some(user) => (
+            ctx.users.role(user).await == Some(Role::Admin),
+            ctx.users.tokens(user).await,
+            ctx.users.language(user).await,
+        ),
+        None => (false, Vec::new(), None),
+    };
+    let tokens: Vec<_> = tokens
+        .into_iter()
+        .map(|t| json!({ "name": bake_string(&t.name), "expires_in_days": t.expires_in_days }))
+        .collect();
     let context = context!(
         base_url => "",
         user => &user.as_ref().map_or(Value::Null, |u| Value::String(bake_string(u))),
+        admin => &admin,
+        tokens => &tokens,
+        preferred_language => &preferred_language.map_or("", Language::code),
+        invite => &invite.as_ref().map_or(Value::Null, |i| Value::String(bake_string(i))),
+        open_registration => &ctx.open_registration,
+        oidc => &ctx.oidc.as_ref().map_or(Value::Null, |o| Value::String(bake_string(o.name()))),
+        csrf_token => &csrf_token,
     );
 
-    let rendered = ctx.templates.read().await.render("login.html", &context);
-    Html::new(rendered)
+    let rendered = ctx
+        .templates
+        .read()
+        .await
+        .render("login.html", language, &context)?;
+    Ok(Html::new(rendered))
 }
 
+/// Answers old slugs and ids with a permanent redirect to the current slug.
 #[actix_web::get("/recipe/{recipe}")]
-#[instrument(skip(ctx))]
+#[instrument(skip(ctx, req))]
 async fn page_recipe(
     ctx: Data<Context>,
-    id: Path<String>,
-    _: Authenticated<NoPermission>,
-) -> Result<Html, Error> {
+    language: Language,
+    key: Path<String>,
+    Authenticated(NoPermission(user)): Authenticated<NoPermission>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let key = key.into_inner();
+    let (id, slug) = ctx.recipes.resolve(&key.to_lowercase()).await?;
+    if key != slug {
+        return Ok(HttpResponse::MovedPermanently()
+            .insert_header((LOCATION, format!("/recipe/{slug}")))
+            .finish());
+    }
     let context = {
-        let id = id.into_inner().to_lowercase();
         let recipe = ctx.recipes.get(&id).await?;
+        // Only logged-in users get the form, and anonymous visitors no session.
+        let csrf_token = user.is_some().then(|| CsrfToken::of(&req).0);
         let mut context = context!(
             base_url => "",
             id => &id,
+            logged_in => &user.is_some(),
+            csrf_token => &csrf_token,
+        );
+        let recipe = recipe.bake(
+            &*ctx.nutrition.data().await,
+            &*ctx.catalogue.data().await,
+            &*ctx.recipes.list().await,
         );
-        context.insert_value("recipe", tera::Value::from_serializable(&recipe.bake()));
+        context.insert_value("recipe", tera::Value::from_serializable(&recipe));
         context
     };
 
@@ -69,45 +221,400 @@ async fn page_recipe(
         .templates
         .read()
         .await
-        .render("recipe-page.html", &context);
+        .render("recipe-page.html", language, &context)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(rendered))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiStep {
+    markdown: String,
+    /// Positions in [`ApiRecipe::ingredients`].
+    ingredients: Vec<usize>,
+}
+
+/// A recipe as stored, not escaped or rendered, with the steps of the instructions and the
+/// ingredients they use.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiRecipe {
+    id: String,
+    slug: String,
+    #[serde(flatten)]
+    recipe: RawRecipe,
+    /// The ingredients of all sections, with those of referenced recipes.
+    ingredients: Vec<Ingredient>,
+    steps: Vec<ApiStep>,
+}
+
+#[actix_web::get("/api/recipes/{recipe}")]
+#[instrument(skip(ctx))]
+async fn api_recipe(
+    ctx: Data<Context>,
+    key: Path<String>,
+    _: Authenticated<NoPermission>,
+) -> Result<Json<ApiRecipe>, Error> {
+    let (id, slug) = ctx.recipes.resolve(&key.to_lowercase()).await?;
+    let recipe = ctx.recipes.get(&id).await?;
+    let ingredients: Vec<_> = recipe
+        .sections(&*ctx.recipes.list().await)
+        .into_iter()
+        .flat_map(|s| s.ingredients)
+        .collect();
+    let (_, steps) = bake_instructions(
+        &recipe.instructions,
+        &ingredients.iter().collect::<Vec<_>>(),
+        1.0,
+        &*ctx.catalogue.data().await,
+    );
+    let steps = steps
+        .into_iter()
+        .map(|s| ApiStep {
+            markdown: s.markdown,
+            ingredients: s.ingredients,
+        })
+        .collect();
+    Ok(Json(ApiRecipe {
+        id,
+        slug,
+        recipe,
+        ingredients,
+        steps,
+    }))
+}
+
+#[derive(Deserialize)]
+struct StepsQuery {
+    /// How the recipe was scaled on the recipe page.
+    factor: Option<f64>,
+}
+
+/// Cooking mode, the instructions one step at a time with the ingredients they use.
+#[actix_web::get("/recipe/{recipe}/steps")]
+#[instrument(skip(ctx))]
+async fn page_steps(
+    ctx: Data<Context>,
+    language: Language,
+    key: Path<String>,
+    Query(StepsQuery { factor }): Query<StepsQuery>,
+) -> Result<HttpResponse, Error> {
+    let key = key.into_inner();
+    let (id, slug) = ctx.recipes.resolve(&key.to_lowercase()).await?;
+    if key != slug {
+        let query = factor.map(|f| format!("?factor={f}")).unwrap_or_default();
+        return Ok(HttpResponse::MovedPermanently()
+            .insert_header((LOCATION, format!("/recipe/{slug}/steps{query}")))
+            .finish());
+    }
+    let factor = factor.unwrap_or(1.0);
+    if !factor.is_finite() || factor <= 0.0 {
+        return Err(Error::InvalidAmount);
+    }
+    let context = {
+        let recipe = ctx.recipes.get(&id).await?;
+        let sections = recipe.sections(&*ctx.recipes.list().await);
+        let ingredients: Vec<_> = sections.iter().flat_map(|s| &s.ingredients).collect();
+        let (_, steps) = bake_instructions(
+            &recipe.instructions,
+            &ingredients,
+            factor,
+            &*ctx.catalogue.data().await,
+        );
+        let ingredients: Vec<_> = ingredients
+            .into_iter()
+            .map(|i| Ingredient {
+                name: bake_string(&i.name),
+                amount: bake_string(&scale_amount(&i.amount, factor)),
+                unit: i.unit.as_deref().map(bake_string),
+                recipe: i.recipe.as_deref().map(bake_string),
+            })
+            .collect();
+        context!(
+            base_url => "",
+            id => &id,
+            slug => &slug,
+            name => &bake_string(&recipe.name),
+            ingredients => &ingredients,
+            steps => &steps,
+        )
+    };
+
+    let rendered = ctx
+        .templates
+        .read()
+        .await
+        .render("steps.html", language, &context)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(rendered))
+}
+
+/// The names of the catalogue, suggested when editing ingredients.
+async fn ingredient_names(ctx: &Context) -> Vec<String> {
+    ctx.catalogue
+        .data()
+        .await
+        .names()
+        .into_iter()
+        .map(bake_string)
+        .collect()
+}
+
+/// The slugs of all recipes, suggested when referencing a recipe.
+async fn recipe_slugs(ctx: &Context) -> Vec<String> {
+    let mut slugs: Vec<_> = ctx
+        .recipes
+        .list()
+        .await
+        .values()
+        .map(|r| bake_string(&to_id_string(&r.name)))
+        .collect();
+    slugs.sort();
+    slugs
+}
+
+#[derive(Deserialize)]
+struct CookQuery {
+    /// Ingredients on hand, separated by commas or lines.
+    have: Option<String>,
+    /// Only sent when checked, so staples are ignored until the form is submitted without it.
+    ignore_staples: Option<String>,
+}
+
+/// Recipes ranked by how many of their ingredients are on hand.
+#[actix_web::get("/cook")]
+async fn page_cook(
+    ctx: Data<Context>,
+    Authenticated(NoPermission(user)): Authenticated<NoPermission>,
+    language: Language,
+    Query(query): Query<CookQuery>,
+) -> Result<Html, Error> {
+    let ignore_staples = query.have.is_none() || query.ignore_staples.is_some();
+    // Starts with the pantry of the user's household.
+    let have = match (query.have, user) {
+        (Some(have), _) => have,
+        (None, Some(user)) => {
+            let household = ctx.users.pantry_key(&user).await;
+            let items = ctx.pantry.items(&household).await;
+            items.into_keys().collect::<Vec<_>>().join("\n")
+        }
+        (None, None) => String::new(),
+    };
+    let context = {
+        let catalogue = ctx.catalogue.data().await;
+        let on_hand: HashSet<String> = have
+            .split([',', '\n'])
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| catalogue.canonical(s))
+            .collect();
+        let recipes = ctx.recipes.list().await;
+        let mut ranked: Vec<_> = recipes
+            .values()
+            .map(|r| {
+                (
+                    r,
+                    r.coverage(&on_hand, &catalogue, &recipes, ignore_staples),
+                )
+            })
+            .filter(|(_, c)| c.covered > 0)
+            .collect();
+        ranked.sort_by(|(a, ca), (b, cb)| {
+            cb.covered
+                .cmp(&ca.covered)
+                .then(ca.missing.len().cmp(&cb.missing.len()))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let results: Vec<_> = ranked
+            .iter()
+            .map(|(r, c)| {
+                json!({
+                    "name": bake_string(&r.name),
+                    "slug": to_id_string(&r.name),
+                    "covered": c.covered,
+                    "total": c.covered + c.missing.len(),
+                    "missing": c.missing.iter().map(|m| bake_string(m)).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        context!(
+            base_url => "",
+            have => &bake_string(&have),
+            searched => &!on_hand.is_empty(),
+            ignore_staples => &ignore_staples,
+            results => &results,
+        )
+    };
+    let rendered = ctx
+        .templates
+        .read()
+        .await
+        .render("cook.html", language, &context)?;
     Ok(Html::new(rendered))
 }
 
-#[actix_web::get("/create")]
-async fn page_create(ctx: Data<Context>, _: Authenticated<NoPermission>) -> Html {
+#[actix_web::get("/pantry")]
+#[instrument(skip(ctx, u, csrf_token), fields(user=u.0.0))]
+async fn page_pantry(
+    ctx: Data<Context>,
+    u: Authenticated<ReadPermission>,
+    language: Language,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Result<Html, Error> {
+    let household = ctx.users.pantry_key(&u.0.0).await;
+    let items: Vec<_> = ctx
+        .pantry
+        .items(&household)
+        .await
+        .iter()
+        .map(|(name, item)| {
+            json!({
+                "name": bake_string(name),
+                "amount": item.amount.to_string(),
+                "unit": bake_string(item.unit.as_deref().unwrap_or_default()),
+            })
+        })
+        .collect();
     let context = context!(
         base_url => "",
+        items => &items,
+        csrf_token => &csrf_token,
+        ingredient_names => &ingredient_names(&ctx).await,
     );
     let rendered = ctx
         .templates
         .read()
         .await
-        .render("edit-recipe-page.html", &context);
-    Html::new(rendered)
+        .render("pantry.html", language, &context)?;
+    Ok(Html::new(rendered))
 }
 
-#[actix_web::get("/edit/{recipe}")]
+#[derive(Deserialize)]
+struct PantryForm {
+    name: String,
+    /// Empty to remove the item.
+    amount: String,
+    unit: String,
+}
+
+#[actix_web::post("/pantry")]
+#[instrument(skip(ctx, u, form), fields(user=u.0.0, name=%form.name))]
+async fn set_pantry_item(
+    ctx: Data<Context>,
+    u: Authenticated<ReadPermission>,
+    Form(form): Form<PantryForm>,
+) -> Result<Redirect, Error> {
+    let item = match form.amount.trim() {
+        "" => None,
+        amount => Some(PantryItem {
+            amount: amount
+                .replace(',', ".")
+                .parse()
+                .map_err(|_| Error::InvalidAmount)?,
+            unit: Some(form.unit.trim().to_owned()).filter(|u| !u.is_empty()),
+        }),
+    };
+    let household = ctx.users.pantry_key(&u.0.0).await;
+    ctx.pantry
+        .set(&household, &form.name, item, &*ctx.catalogue.data().await)
+        .await?;
+    Ok(Redirect::to("/pantry").see_other())
+}
+
+#[derive(Deserialize)]
+struct CookedForm {
+    /// How the recipe was scaled on the recipe page.
+    factor: Option<f64>,
+}
+
+#[actix_web::post("/cooked/{recipe}")]
 #[instrument(skip(ctx, u), fields(user=u.0.0))]
-async fn page_edit(
+async fn mark_cooked(
     ctx: Data<Context>,
+    u: Authenticated<ReadPermission>,
     id: Path<String>,
+    Form(CookedForm { factor }): Form<CookedForm>,
+) -> Result<Redirect, Error> {
+    let factor = factor.unwrap_or(1.0);
+    if !factor.is_finite() || factor <= 0.0 {
+        return Err(Error::InvalidAmount);
+    }
+    let recipe = ctx.recipes.get(&id).await?;
+    let household = ctx.users.pantry_key(&u.0.0).await;
+    ctx.pantry
+        .cook(
+            &household,
+            &recipe,
+            factor,
+            &*ctx.recipes.list().await,
+            &*ctx.catalogue.data().await,
+        )
+        .await?;
+    Ok(Redirect::to("/pantry").see_other())
+}
+
+#[actix_web::get("/create")]
+async fn page_create(
+    ctx: Data<Context>,
+    language: Language,
+    _: Authenticated<NoPermission>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Result<Html, Error> {
+    let context = context!(
+        base_url => "",
+        csrf_token => &csrf_token,
+        ingredient_names => &ingredient_names(&ctx).await,
+        recipe_slugs => &recipe_slugs(&ctx).await,
+    );
+    let rendered =
+        ctx.templates
+            .read()
+            .await
+            .render("edit-recipe-page.html", language, &context)?;
+    Ok(Html::new(rendered))
+}
+
+#[actix_web::get("/edit/{recipe}")]
+#[instrument(skip(ctx, u, csrf_token), fields(user=u.0.0))]
+async fn page_edit(
+    ctx: Data<Context>,
+    language: Language,
+    key: Path<String>,
     u: Authenticated<NoPermission>,
+    CsrfToken(csrf_token): CsrfToken,
 ) -> Result<Html, Error> {
-    let id = id.into_inner().to_lowercase();
-    let recipe = ctx.recipes.get(&id).await?;
+    let (id, slug) = ctx
+        .recipes
+        .resolve(&key.into_inner().to_lowercase())
+        .await?;
+    let mut recipe = ctx.recipes.get(&id).await?;
+    // References are edited by the current slug of the recipe, not its id.
+    {
+        let recipes = ctx.recipes.list().await;
+        for (_, reference) in recipe.references_mut() {
+            if let Some(other) = reference.as_deref().and_then(|r| recipes.get(r)) {
+                *reference = Some(to_id_string(&other.name));
+            }
+        }
+    }
     let context = {
         let mut context = context!(
             base_url => "",
             id => &id,
+            slug => &slug,
+            csrf_token => &csrf_token,
+            ingredient_names => &ingredient_names(&ctx).await,
+            recipe_slugs => &recipe_slugs(&ctx).await,
         );
         context.insert_value("recipe", tera::Value::from_serializable(&recipe));
         context
     };
-    let rendered = ctx
-        .templates
-        .read()
-        .await
-        .render("edit-recipe-page.html", &context);
+    let rendered =
+        ctx.templates
+            .read()
+            .await
+            .render("edit-recipe-page.html", language, &context)?;
     Ok(Html::new(rendered))
 }
 
@@ -118,13 +625,15 @@ async fn create(
     u: Authenticated<WritePermission>,
     Json(mut recipe): Json<RawRecipe>,
 ) -> Result<Redirect, Error> {
-    recipe.clean();
-    let id = to_id_string(&recipe.name);
-    if id.is_empty() {
-        return Err(Error::EmptyId);
+    recipe.clean(&*ctx.catalogue.data().await);
+    let mut errors = recipe.validate();
+    errors.extend(ctx.nutrition.data().await.validate(&recipe));
+    errors.extend(ctx.recipes.link(None, &mut recipe).await);
+    if !errors.is_empty() {
+        return Err(Error::InvalidRecipe(errors));
     }
-    let url = format!("/recipe/{id}");
-    ctx.recipes.create(id, recipe).await?;
+    let url = format!("/recipe/{}", to_id_string(&recipe.name));
+    ctx.recipes.create(generate_id(), recipe).await?;
     Ok(Redirect::to(url).see_other())
 }
 
@@ -137,13 +646,15 @@ async fn edit(
     Json(mut recipe): Json<RawRecipe>,
 ) -> Result<Redirect, Error> {
     let id = id.into_inner();
-    recipe.clean();
-    let new_id = to_id_string(&recipe.name);
-    if new_id.is_empty() {
-        return Err(Error::EmptyId);
+    recipe.clean(&*ctx.catalogue.data().await);
+    let mut errors = recipe.validate();
+    errors.extend(ctx.nutrition.data().await.validate(&recipe));
+    errors.extend(ctx.recipes.link(Some(&id), &mut recipe).await);
+    if !errors.is_empty() {
+        return Err(Error::InvalidRecipe(errors));
     }
-    let url = format!("/recipe/{new_id}");
-    ctx.recipes.replace(&id, new_id, recipe).await?;
+    let url = format!("/recipe/{}", to_id_string(&recipe.name));
+    ctx.recipes.replace(&id, recipe).await?;
     Ok(Redirect::to(url).see_other())
 }
 
@@ -165,16 +676,74 @@ struct LoginForm {
     password: String,
 }
 
+#[derive(Deserialize)]
+struct RegisterForm {
+    user: String,
+    password: String,
+    invite: Option<String>,
+}
+
 #[actix_web::post("/register")]
-#[instrument(skip(ctx, password))]
+#[instrument(skip(ctx, password, invite))]
 async fn register(
     ctx: Data<Context>,
-    Form(LoginForm { user, password }): Form<LoginForm>,
+    Form(RegisterForm {
+        user,
+        password,
+        invite,
+    }): Form<RegisterForm>,
 ) -> Result<Redirect, Error> {
-    ctx.users.register(user, password).await?;
+    if invite.is_none() && !ctx.open_registration {
+        return Err(Error::RegistrationClosed);
+    }
+    ctx.users.register(user, password, invite).await?;
     Ok(Redirect::to("/").see_other())
 }
 
+/// The longest an invite or API token can be valid, about ten years.
+const MAX_DAYS: u64 = 3_650;
+
+/// A number of days from a form, from one up to [`MAX_DAYS`].
+fn days_duration(days: u64) -> Result<Duration, Error> {
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(Error::InvalidDays);
+    }
+    Ok(Duration::from_hours(days * 24))
+}
+
+#[derive(Deserialize)]
+struct InviteForm {
+    role: Role,
+    days: u64,
+}
+
+#[actix_web::post("/invite")]
+#[instrument(skip(ctx, u, req), fields(user=u.0.0))]
+async fn create_invite(
+    ctx: Data<Context>,
+    language: Language,
+    u: Authenticated<AdminPermission>,
+    Form(InviteForm { role, days }): Form<InviteForm>,
+    req: HttpRequest,
+) -> Result<Html, Error> {
+    let token = ctx.users.create_invite(role, days_duration(days)?).await?;
+    let url = {
+        let info = req.connection_info();
+        format!("{}://{}/login?invite={token}", info.scheme(), info.host())
+    };
+    let context = context!(
+        base_url => "",
+        url => &bake_string(&url),
+        days => &days,
+    );
+    let rendered = ctx
+        .templates
+        .read()
+        .await
+        .render("invite.html", language, &context)?;
+    Ok(Html::new(rendered))
+}
+
 #[actix_web::post("/login")]
 #[instrument(skip(ctx, password, req))]
 async fn login(
@@ -186,6 +755,141 @@ async fn login(
     Ok(Redirect::to("/").see_other())
 }
 
+/// HTML forms submit empty number inputs as an empty string.
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err: Display>,
+{
+    let s = String::deserialize(deserializer)?;
+    let s = s.trim();
+    if s.is_empty() {
+        Ok(None)
+    } else {
+        s.parse().map(Some).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    name: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    days: Option<u64>,
+}
+
+#[actix_web::post("/tokens")]
+#[instrument(skip(ctx, u), fields(user=u.0.0))]
+async fn create_token(
+    ctx: Data<Context>,
+    language: Language,
+    u: Authenticated<ReadPermission>,
+    Form(TokenForm { name, days }): Form<TokenForm>,
+) -> Result<Html, Error> {
+    let name = name.trim().to_owned();
+    let valid_for = days.map(days_duration).transpose()?;
+    let token = ctx
+        .users
+        .create_token(&u.0.0, name.clone(), valid_for)
+        .await?;
+    let context = context!(
+        base_url => "",
+        name => &bake_string(&name),
+        token => &token,
+    );
+    let rendered = ctx
+        .templates
+        .read()
+        .await
+        .render("api-token.html", language, &context)?;
+    Ok(Html::new(rendered))
+}
+
+#[derive(Deserialize)]
+struct LanguageForm {
+    /// Empty to use the language requested by the browser.
+    language: String,
+}
+
+#[actix_web::post("/language")]
+#[instrument(skip(ctx, u), fields(user=u.0.0))]
+async fn set_language(
+    ctx: Data<Context>,
+    u: Authenticated<ReadPermission>,
+    Form(LanguageForm { language }): Form<LanguageForm>,
+) -> Result<Redirect, Error> {
+    ctx.users
+        .set_language(&u.0.0, Language::from_code(&language))
+        .await?;
+    Ok(Redirect::to("/login").see_other())
+}
+
+#[derive(Deserialize)]
+struct RevokeTokenForm {
+    name: String,
+}
+
+#[actix_web::post("/tokens/revoke")]
+#[instrument(skip(ctx, u), fields(user=u.0.0))]
+async fn revoke_token(
+    ctx: Data<Context>,
+    u: Authenticated<ReadPermission>,
+    Form(RevokeTokenForm { name }): Form<RevokeTokenForm>,
+) -> Result<Redirect, Error> {
+    ctx.users.revoke_token(&u.0.0, &name).await?;
+    Ok(Redirect::to("/login").see_other())
+}
+
+#[actix_web::get("/oidc/login")]
+#[instrument(skip(ctx))]
+async fn oidc_login(
+    ctx: Data<Context>,
+    Authenticated(NoPermission(user)): Authenticated<NoPermission>,
+) -> Result<HttpResponse, Error> {
+    let oidc = ctx.oidc.as_ref().ok_or(Error::NotFound)?;
+    let (url, state) = oidc.authorization_url(user).await?;
+    // Lax instead of the session's Strict, so it is sent when the provider redirects back.
+    let cookie = Cookie::build(STATE_COOKIE, state)
+        .path("/oidc")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(PENDING_DURATION.try_into().unwrap())
+        .finish();
+    Ok(HttpResponse::SeeOther()
+        .insert_header((LOCATION, url))
+        .cookie(cookie)
+        .finish())
+}
+
+#[derive(Deserialize)]
+struct OidcCallback {
+    code: String,
+    state: String,
+}
+
+#[actix_web::get("/oidc/callback")]
+#[instrument(skip_all)]
+async fn oidc_callback(
+    ctx: Data<Context>,
+    Query(OidcCallback { code, state }): Query<OidcCallback>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let oidc = ctx.oidc.as_ref().ok_or(Error::NotFound)?;
+    let browser_state = req.cookie(STATE_COOKIE);
+    let user = oidc
+        .authenticate(&code, &state, browser_state.as_ref().map(Cookie::value))
+        .await?;
+    ctx.users
+        .login_external(user, oidc.register_role(), &req)
+        .await?;
+    let mut removal = Cookie::build(STATE_COOKIE, "").path("/oidc").finish();
+    removal.make_removal();
+    Ok(HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/"))
+        .cookie(removal)
+        .finish())
+}
+
 #[actix_web::post("/invalidate-sessions")]
 #[instrument(skip(ctx, req))]
 async fn invalidate_sessions(
@@ -197,15 +901,153 @@ async fn invalidate_sessions(
     Ok(Redirect::to("/").see_other())
 }
 
+#[actix_web::get("/admin")]
+#[instrument(skip(ctx, u, csrf_token), fields(user=u.0.0))]
+async fn page_admin(
+    ctx: Data<Context>,
+    u: Authenticated<AdminPermission>,
+    language: Language,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Result<Html, Error> {
+    let template_error = ctx.template_error.read().await.as_deref().map(bake_string);
+    let catalogue: Vec<_> = ctx
+        .catalogue
+        .data()
+        .await
+        .ingredients()
+        .iter()
+        .map(|(name, e)| {
+            json!({
+                "name": bake_string(name),
+                "plural": bake_string(e.plural.as_deref().unwrap_or_default()),
+                "synonyms": bake_string(&e.synonyms.join(", ")),
+                "density": e.density.map(|d| d.to_string()).unwrap_or_default(),
+                "staple": e.staple,
+            })
+        })
+        .collect();
+    let context = context!(
+        base_url => "",
+        template_error => &template_error,
+        catalogue => &catalogue,
+        csrf_token => &csrf_token,
+    );
+    let rendered = ctx
+        .templates
+        .read()
+        .await
+        .render("admin.html", language, &context)?;
+    Ok(Html::new(rendered))
+}
+
+#[derive(Deserialize)]
+struct CatalogueForm {
+    name: String,
+    plural: String,
+    /// Separated by commas.
+    synonyms: String,
+    density: String,
+    /// Only sent when checked.
+    staple: Option<String>,
+}
+
+#[actix_web::post("/admin/catalogue")]
+#[instrument(skip(ctx, u, form), fields(user=u.0.0, name=%form.name))]
+async fn save_catalogue_entry(
+    ctx: Data<Context>,
+    u: Authenticated<AdminPermission>,
+    Form(form): Form<CatalogueForm>,
+) -> Result<Redirect, Error> {
+    let density = match form.density.trim() {
+        "" => None,
+        d => Some(
+            d.replace(',', ".")
+                .parse()
+                .map_err(|_| Error::InvalidDensity)?,
+        ),
+    };
+    let entry = CatalogueEntry {
+        plural: Some(form.plural),
+        synonyms: form.synonyms.split(',').map(str::to_owned).collect(),
+        density,
+        staple: form.staple.is_some(),
+    };
+    ctx.catalogue.save(&form.name, entry).await?;
+    Ok(Redirect::to("/admin").see_other())
+}
+
+#[derive(Deserialize)]
+struct RemoveCatalogueEntryForm {
+    name: String,
+}
+
+#[actix_web::post("/admin/catalogue/remove")]
+#[instrument(skip(ctx, u), fields(user=u.0.0))]
+async fn remove_catalogue_entry(
+    ctx: Data<Context>,
+    u: Authenticated<AdminPermission>,
+    Form(RemoveCatalogueEntryForm { name }): Form<RemoveCatalogueEntryForm>,
+) -> Result<Redirect, Error> {
+    ctx.catalogue.remove(&name).await?;
+    Ok(Redirect::to("/admin").see_other())
+}
+
+#[actix_web::get("/healthz")]
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+#[actix_web::get("/readyz")]
+async fn readyz(ctx: Data<Context>) -> HttpResponse {
+    if ctx.recipes.is_available().await && ctx.users.is_available().await {
+        HttpResponse::Ok().body("ok")
+    } else {
+        HttpResponse::ServiceUnavailable().body("storage unavailable")
+    }
+}
+
+#[actix_web::get("/metrics")]
+async fn metrics(ctx: Data<Context>) -> HttpResponse {
+    let recipes = ctx.recipes.list().await.len();
+    let users = ctx.users.count().await;
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(METRICS.render(recipes, users))
+}
+
+/// The default service for unknown paths.
+pub(crate) async fn not_found() -> Result<HttpResponse, Error> {
+    Err(Error::NotFound)
+}
+
 pub(crate) fn configure(c: &mut ServiceConfig) {
-    c.service(page_home)
+    c.service(healthz)
+        .service(readyz)
+        .service(metrics)
+        .service(page_home)
+        .service(api_recipes)
+        .service(api_recipe)
+        .service(page_cook)
+        .service(page_pantry)
+        .service(set_pantry_item)
+        .service(mark_cooked)
+        .service(page_admin)
+        .service(save_catalogue_entry)
+        .service(remove_catalogue_entry)
         .service(page_login)
         .service(page_recipe)
+        .service(page_steps)
         .service(page_create)
         .service(page_edit)
         .service(register)
+        .service(create_invite)
         .service(login)
+        .service(oidc_login)
+        .service(oidc_callback)
         .service(invalidate_sessions)
+        .service(create_token)
+        .service(revoke_token)
+        .service(set_language)
         .service(create)
         .service(edit)
         .service(delete);
@@ -214,29 +1056,65 @@ pub(crate) fn configure(c: &mut ServiceConfig) {
 #[cfg(test)]
 pub(crate) mod tests {
     use std::path::{Path, PathBuf};
+    use std::time::Duration;
 
     use actix_web::body::MessageBody;
     use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{ACCEPT, ACCEPT_LANGUAGE, AUTHORIZATION, LOCATION, SET_COOKIE};
+    use actix_web::middleware::from_fn;
     use actix_web::web::Data;
     use actix_web::{App, Error, http::header::ContentType, test};
+    use serde_json::{Value, json};
     use tokio::sync::RwLock;
 
     use crate::auth::Users;
+    use crate::catalogue::Catalogue;
     use crate::context::Context;
+    use crate::nutrition::Nutrition;
+    use crate::pantry::Pantry;
+    use crate::recipe::{Ingredient, IngredientsSection, RawRecipe};
     use crate::recipes::Recipes;
     use crate::templates::Templates;
 
     use super::configure;
 
+    const SESSION_DURATION: Duration = Duration::from_hours(24);
+
     pub(crate) async fn make_app_data() -> Data<Context> {
-        let recipes = Recipes::load_dir(Path::new("tests/recipes")).await;
-        let users = Users::load(Path::new("tests/users.json").into()).await;
-        let templates = RwLock::new(Templates::load_directory(PathBuf::from("templates/")).await);
-        Data::new(Context {
+        let users = Users::load(Path::new("tests/users.json").into(), 4)
+            .await
+            .unwrap();
+        make_app_data_with_users(users).await
+    }
+
+    pub(crate) async fn make_app_data_with_users(users: Users) -> Data<Context> {
+        Data::new(make_context(users).await)
+    }
+
+    pub(crate) async fn make_context(users: Users) -> Context {
+        let recipes = Recipes::load_dir(Path::new("tests/recipes")).await.unwrap();
+        let templates = Templates::load_directory(PathBuf::from("templates/"))
+            .await
+            .unwrap();
+        let templates = RwLock::new(templates);
+        Context {
             templates,
+            template_error: RwLock::new(None),
             recipes,
             users,
-        })
+            nutrition: Nutrition::load(PathBuf::from("tests/nutrition.json"))
+                .await
+                .unwrap(),
+            catalogue: Catalogue::load(PathBuf::from("tests/catalogue.json"))
+                .await
+                .unwrap(),
+            pantry: Pantry::load(PathBuf::from("tests/pantry.json"))
+                .await
+                .unwrap(),
+            open_registration: true,
+            oidc: None,
+        }
     }
 
     pub(crate) async fn app() -> App<
@@ -247,13 +1125,37 @@ pub(crate) mod tests {
             Error = Error,
             InitError = (),
         >,
+    > {
+        app_with(make_app_data().await)
+    }
+
+    pub(crate) fn app_with(
+        data: Data<Context>,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
     > {
         App::new()
-            .app_data(make_app_data().await)
+            .app_data(data)
+            .wrap(from_fn(crate::csrf::verify))
+            .wrap(from_fn(crate::error::error_page))
             .wrap(crate::middlewares::tracing())
-            .wrap(crate::middlewares::identity())
-            .wrap(crate::middlewares::session(&[0; 64]))
+            .wrap(crate::middlewares::identity(SESSION_DURATION))
+            .wrap(crate::middlewares::session(&[0; 64], SESSION_DURATION))
             .configure(configure)
+            .default_service(actix_web::web::to(super::not_found))
+    }
+
+    pub(crate) fn csrf_token_of(body: &str) -> &str {
+        let (_, rest) = body
+            .split_once(r#"name="csrf_token" value=""#)
+            .expect("expecting csrf token");
+        rest.split_once('"').unwrap().0
     }
 
     #[actix_web::test]
@@ -276,6 +1178,107 @@ pub(crate) mod tests {
         assert!(resp.status().is_success());
     }
 
+    #[actix_web::test]
+    async fn test_api_recipes() {
+        let app = test::init_service(app().await).await;
+        let names = async |uri| {
+            let req = test::TestRequest::with_uri(uri).to_request();
+            let recipes: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+            recipes
+                .iter()
+                .map(|r| r["Name"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("/api/recipes").await, ["Test 1", "Test 2"]);
+        assert_eq!(names("/api/recipes?max_time=20").await, [""; 0]);
+        assert_eq!(names("/api/recipes?max_time=25&q=test").await, ["Test 1"]);
+
+        let req = test::TestRequest::with_uri("/api/recipes?sort=total-time").to_request();
+        let recipes: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(recipes[0]["TotalTime"], 25);
+        assert_eq!(recipes[1]["TotalTime"], Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn test_api_recipe() {
+        let app = test::init_service(app().await).await;
+        let req = test::TestRequest::with_uri("/api/recipes/Test-1").to_request();
+        let recipe: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(recipe["Name"], "Test 1");
+        assert_eq!(recipe["Slug"], "test-1");
+        assert_eq!(recipe["Description"], "Beschreibung & mehr");
+        assert_eq!(recipe["Ingredients"].as_array().unwrap().len(), 5);
+        assert_eq!(recipe["Ingredients"][4]["Name"], "Zutat 3");
+        let steps = recipe["Steps"].as_array().unwrap();
+        assert_eq!(steps.len(), 12);
+        assert_eq!(steps[0], json!({"Markdown": "Absatz 1", "Ingredients": []}));
+        assert_eq!(steps[2]["Markdown"], "Eintrag 1");
+        assert_eq!(steps[6]["Markdown"], "# H1\n\nText");
+
+        let req = test::TestRequest::with_uri("/api/recipes/unknown").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_cook_page() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let recipes = dir.path().join("recipes");
+        std::fs::create_dir(&recipes).unwrap();
+        for (id, name, ingredients) in [
+            ("r1", "Kuchen", &["Mehl", "Eier", "Zucker", "Butter"][..]),
+            ("r2", "Pfannkuchen", &["Mehl", "Eier", "Salz"][..]),
+            ("r3", "Suppe", &["Karotten", "Wasser"][..]),
+        ] {
+            let recipe = RawRecipe {
+                name: name.into(),
+                ingredients_sections: vec![IngredientsSection {
+                    ingredients: ingredients
+                        .iter()
+                        .map(|name| Ingredient {
+                            name: (*name).into(),
+                            amount: "1".into(),
+                            unit: None,
+                            recipe: None,
+                        })
+                        .collect(),
+                    ..IngredientsSection::default()
+                }],
+                ..RawRecipe::default()
+            };
+            let recipe = serde_json::to_string(&recipe).unwrap();
+            std::fs::write(recipes.join(format!("{id}.json")), recipe).unwrap();
+        }
+        let catalogue = dir.path().join("catalogue.json");
+        let entries = json!({ "Ingredients": { "Salz": { "Staple": true } } });
+        std::fs::write(&catalogue, entries.to_string()).unwrap();
+        let users = Users::load(Path::new("tests/users.json").into(), 4)
+            .await
+            .unwrap();
+        let mut context = make_context(users).await;
+        context.recipes = Recipes::load_dir(&recipes).await.unwrap();
+        context.catalogue = Catalogue::load(catalogue).await.unwrap();
+        let app = test::init_service(app_with(Data::new(context))).await;
+
+        // Pfannkuchen is fully covered with salt as staple, Kuchen lacks two ingredients and
+        // Suppe has none on hand.
+        let req =
+            test::TestRequest::with_uri("/cook?have=Mehl%2C+Eier&ignore_staples=on").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        let pancakes = body.find(">Pfannkuchen</a>\n            (2/2)").unwrap();
+        let cake = body.find(">Kuchen</a>\n            (2/4)").unwrap();
+        assert!(pancakes < cake);
+        assert!(body[cake..].contains("Zucker, Butter"));
+        assert!(!body[pancakes..cake].contains("<small>"));
+        assert!(!body.contains("Suppe"));
+
+        let req = test::TestRequest::with_uri("/cook?have=Mehl%2C+Eier").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(">Pfannkuchen</a>\n            (2/3)"));
+    }
+
     #[actix_web::test]
     async fn test_create_page() {
         let app = test::init_service(app().await).await;
@@ -294,6 +1297,36 @@ pub(crate) mod tests {
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
+        // Anonymous visitors do not get a session.
+        assert!(resp.headers().get(SET_COOKIE).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_recipe_redirect() {
+        let app = test::init_service(app().await).await;
+        for uri in ["/recipe/old-test-2", "/recipe/Test-2"] {
+            let req = test::TestRequest::with_uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY, "{uri}");
+            assert_eq!(resp.headers().get(LOCATION).unwrap(), "/recipe/test-2");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_steps_page() {
+        let app = test::init_service(app().await).await;
+        let req = test::TestRequest::with_uri("/recipe/test-1/steps?factor=2").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert_eq!(body.matches(r#"<section class="step""#).count(), 12);
+        assert!(body.contains("4 E Zutat 2"));
+
+        let req = test::TestRequest::with_uri("/recipe/old-test-2/steps?factor=0.5").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        let location = resp.headers().get(LOCATION).unwrap();
+        assert_eq!(location, "/recipe/test-2/steps?factor=0.5");
     }
 
     #[actix_web::test]
@@ -305,4 +1338,135 @@ pub(crate) mod tests {
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
+
+    #[actix_web::test]
+    async fn test_probes() {
+        let app = test::init_service(app().await).await;
+        for uri in ["/healthz", "/readyz"] {
+            let req = test::TestRequest::with_uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+        }
+
+        let req = test::TestRequest::with_uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"route="/healthz",status="200"} "#));
+        assert!(body.contains("recipes_recipes 2\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_admin_page() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("users.json");
+        std::fs::copy("tests/users.json", &path).unwrap();
+        let users = Users::load(path, 4).await.unwrap();
+        let token = users
+            .create_token("admin", "test".into(), None)
+            .await
+            .unwrap();
+        let context = make_context(users).await;
+        *context.template_error.write().await = Some("broken <template>".into());
+        let app = test::init_service(app_with(Data::new(context))).await;
+
+        let req = test::TestRequest::with_uri("/admin").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::with_uri("/admin")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("broken &lt;template&gt;"));
+    }
+
+    #[actix_web::test]
+    async fn test_expiry_days() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("users.json");
+        std::fs::copy("tests/users.json", &path).unwrap();
+        let users = Users::load(path, 4).await.unwrap();
+        let token = users
+            .create_token("admin", "test".into(), None)
+            .await
+            .unwrap();
+        let app = test::init_service(app_with(Data::new(make_context(users).await))).await;
+
+        for (days, status) in [
+            ("7", StatusCode::OK),
+            ("0", StatusCode::BAD_REQUEST),
+            ("3651", StatusCode::BAD_REQUEST),
+            ("999999999999999", StatusCode::BAD_REQUEST),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/invite")
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .set_form([("role", "reader"), ("days", days)])
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{days}");
+            let req = test::TestRequest::post()
+                .uri("/tokens")
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .set_form([("name", days), ("days", days)])
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{days}");
+            if status == StatusCode::BAD_REQUEST {
+                let body = test::read_body(resp).await;
+                assert_eq!(body, "invalid-days");
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_error_page() {
+        let app = test::init_service(app().await).await;
+
+        let req = test::TestRequest::with_uri("/recipe/missing")
+            .insert_header((ACCEPT, "text/html,application/xhtml+xml"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<h1>Nicht gefunden</h1>"));
+        assert!(body.contains("not-found"));
+
+        let req = test::TestRequest::with_uri("/unknown")
+            .insert_header((ACCEPT, "text/html"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(
+            std::str::from_utf8(&body)
+                .unwrap()
+                .contains("Nicht gefunden")
+        );
+
+        let req = test::TestRequest::with_uri("/recipe/missing")
+            .insert_header((ACCEPT, "*/*"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(&body[..], b"not-found");
+    }
+
+    #[actix_web::test]
+    async fn test_language() {
+        let app = test::init_service(app().await).await;
+
+        let req = test::TestRequest::with_uri("/login").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"<html lang="de">"#));
+        assert!(body.contains("<h2>Einloggen</h2>"));
+
+        let req = test::TestRequest::with_uri("/login")
+            .insert_header((ACCEPT_LANGUAGE, "en-GB,en;q=0.9"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"<html lang="en">"#));
+        assert!(body.contains("<h2>Log in</h2>"));
+    }
 }
@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use config::Environment;
+use serde::Deserialize;
+
+use crate::oidc::OidcConfig;
+
+/// The configuration file, overridable by `RECIPES_*` environment variables.
+#[derive(Deserialize)]
+pub struct Config {
+    pub address: String,
+    /// At least 64 bytes.
+    pub cookies_key: String,
+    #[serde(default)]
+    pub paths: Paths,
+    /// How long a login stays valid, up to ten years.
+    #[serde(default = "default_session_days")]
+    pub session_days: u64,
+    #[serde(default = "default_bcrypt_cost")]
+    pub bcrypt_cost: u32,
+    /// Allow registering without an invite. Such users still have to be unlocked manually.
+    #[serde(default = "default_open_registration")]
+    pub open_registration: bool,
+    /// Serve HTTPS instead of HTTP.
+    pub tls: Option<TlsConfig>,
+    pub oidc: Option<OidcConfig>,
+    #[serde(default)]
+    pub logging: LogConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Paths {
+    pub recipes: PathBuf,
+    pub templates: PathBuf,
+    pub users: PathBuf,
+    pub static_files: PathBuf,
+    /// Created when foods are imported.
+    pub nutrition: PathBuf,
+    /// Created when an admin edits the ingredient catalogue.
+    pub catalogue: PathBuf,
+    /// Created when a household first stores something.
+    pub pantry: PathBuf,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths {
+            recipes: "recipes".into(),
+            templates: "templates".into(),
+            users: "users.json".into(),
+            static_files: "static".into(),
+            nutrition: "nutrition.json".into(),
+            catalogue: "catalogue.json".into(),
+            pantry: "pantry.json".into(),
+        }
+    }
+}
+
+/// The certificate files are reloaded when they change.
+#[derive(Debug, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain.
+    pub certificate: PathBuf,
+    /// PEM file with the private key.
+    pub key: PathBuf,
+    /// Also listen for plain HTTP here and redirect it to HTTPS.
+    pub redirect_address: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// OTLP/HTTP endpoint to export traces to, e.g. `http://localhost:4318/v1/traces`.
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+const MAX_SESSION_DAYS: u64 = 3_650;
+
+fn default_session_days() -> u64 {
+    30
+}
+
+fn default_bcrypt_cost() -> u32 {
+    bcrypt::DEFAULT_COST
+}
+
+fn default_open_registration() -> bool {
+    true
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, config::ConfigError> {
+        let config = config::Config::builder()
+            .add_source(config::File::from(path))
+            .add_source(
+                Environment::with_prefix("RECIPES")
+                    .prefix_separator("_")
+                    .separator("__"),
+            )
+            .build()?;
+
+        let config: Config = config.try_deserialize()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Rejects values the server would otherwise fail on at startup or first use.
+    fn validate(&self) -> Result<(), config::ConfigError> {
+        let error = |message: &str| Err(config::ConfigError::Message(message.into()));
+        if self.cookies_key.len() < 64 {
+            return error("cookies_key must be at least 64 bytes long");
+        }
+        if !(1..=MAX_SESSION_DAYS).contains(&self.session_days) {
+            return error(&format!(
+                "session_days must be between 1 and {MAX_SESSION_DAYS}"
+            ));
+        }
+        if !(4..=31).contains(&self.bcrypt_cost) {
+            return error("bcrypt_cost must be between 4 and 31");
+        }
+        Ok(())
+    }
+
+    pub fn session_duration(&self) -> Duration {
+        Duration::from_hours(self.session_days * 24)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::Config;
+
+    const KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_defaults() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("Recipes.toml");
+        std::fs::write(
+            &path,
+            format!("address = \"0.0.0.0:4200\"\ncookies_key = \"{KEY}\"\n"),
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.paths.recipes.to_str(), Some("recipes"));
+        assert_eq!(config.session_duration().as_secs(), 30 * 24 * 60 * 60);
+        assert_eq!(config.bcrypt_cost, bcrypt::DEFAULT_COST);
+        assert!(config.open_registration);
+        assert!(config.tls.is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("Recipes.toml");
+        for (extra, valid) in [
+            ("", true),
+            ("session_days = 3650\nbcrypt_cost = 4", true),
+            ("session_days = 0", false),
+            ("session_days = 99999999999999", false),
+            ("bcrypt_cost = 3", false),
+            ("bcrypt_cost = 32", false),
+        ] {
+            let content = format!("address = \"\"\ncookies_key = \"{KEY}\"\n{extra}\n");
+            std::fs::write(&path, content).unwrap();
+            assert_eq!(Config::load(&path).is_ok(), valid, "{extra}");
+        }
+        std::fs::write(&path, "address = \"\"\ncookies_key = \"short\"\n").unwrap();
+        assert!(Config::load(&path).is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use std::cmp::Reverse;
+use std::ops::Range;
+use std::str::FromStr;
+
+use comrak::html::escape;
+use comrak::nodes::{AstNode, ListType, NodeValue};
+use comrak::{Arena, Options, create_formatter, format_commonmark, parse_document};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::catalogue::CatalogueData;
+use crate::duration::find_durations;
+use crate::recipe::{Ingredient, bake_string, md_options, scale_amount};
+use crate::unit::unit_needs_space;
+
+/// One step of the instructions, as shown in cooking mode.
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Debug, PartialEq, Eq))]
+#[serde(rename_all = "PascalCase")]
+pub struct Step {
+    /// Rendered like the instructions, including the headings right before the step.
+    pub html: String,
+    /// The same as Markdown, without the amounts added to mentions.
+    pub markdown: String,
+    /// Positions of the ingredients mentioned in the step, counted across all sections.
+    pub ingredients: Vec<usize>,
+}
+
+/// Matches any spelling of an ingredient as a whole word, ignoring case. `None` for blank names,
+/// which would match at every word boundary.
+fn mention_pattern(ingredient: &Ingredient, catalogue: &CatalogueData) -> Option<Regex> {
+    let mut spellings = catalogue.spellings(&ingredient.name);
+    if spellings.is_empty() {
+        return None;
+    }
+    // Longer spellings first, so a plural is not cut short by the singular.
+    spellings.sort_by_key(|s| Reverse(s.len()));
+    let alternatives: Vec<String> = spellings.iter().map(|s| regex::escape(s)).collect();
+    Some(Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives.join("|"))).unwrap())
+}
+
+/// The amount shown after a mention, like ` (200 ml)`. Numbers are marked so the recipe page
+/// can scale them.
+fn amount_html(ingredient: &Ingredient, factor: f64) -> Option<String> {
+    let amount = ingredient.amount.trim();
+    if amount.is_empty() {
+        return None;
+    }
+    let amount = match f64::from_str(amount) {
+        Ok(_) => {
+            let scaled = scale_amount(amount, factor);
+            format!(r#"<span class="mention-amount" data-amount="{scaled}">{scaled}</span>"#)
+        }
+        Err(_) => bake_string(amount),
+    };
+    let unit = match ingredient.unit.as_deref().filter(|u| !u.is_empty()) {
+        Some(unit) if unit_needs_space(unit) => format!(" {}", bake_string(unit)),
+        Some(unit) => bake_string(unit),
+        None => String::new(),
+    };
+    Some(format!(
+        r#" <span class="ingredient-mention">({amount}{unit})</span>"#
+    ))
+}
+
+/// Tracks the ingredients mentioned while rendering. Only the first mention of an ingredient
+/// gets its amount, so an ingredient listed in two sections is shown with the first amount at
+/// the first mention and the second amount at the next one.
+#[derive(Default)]
+struct Mentions {
+    patterns: Vec<Option<Regex>>,
+    amounts: Vec<Option<String>>,
+    annotated: Vec<bool>,
+    /// The ingredients mentioned since the last step.
+    found: Vec<usize>,
+}
+
+impl Mentions {
+    fn new(ingredients: &[&Ingredient], factor: f64, catalogue: &CatalogueData) -> Self {
+        Mentions {
+            patterns: ingredients
+                .iter()
+                .map(|i| mention_pattern(i, catalogue))
+                .collect(),
+            amounts: ingredients.iter().map(|i| amount_html(i, factor)).collect(),
+            annotated: vec![false; ingredients.len()],
+            found: Vec::new(),
+        }
+    }
+
+    /// The mentions in a text outside of `taken`, with the ingredient whose amount belongs after
+    /// them. Where mentions overlap, the longest wins, like `grobes Salz` over `Salz`.
+    fn find(&mut self, text: &str, taken: &[Range<usize>]) -> Vec<(Range<usize>, Option<usize>)> {
+        let mut candidates: Vec<(Range<usize>, Vec<usize>)> = Vec::new();
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            let Some(pattern) = pattern else {
+                continue;
+            };
+            // Amounts only go after actual words, never between them.
+            for m in pattern.find_iter(text).filter(|m| !m.is_empty()) {
+                match candidates.iter_mut().find(|(r, _)| *r == m.range()) {
+                    Some((_, ingredients)) => ingredients.push(i),
+                    None => candidates.push((m.range(), vec![i])),
+                }
+            }
+        }
+        candidates.sort_by_key(|(r, _)| (Reverse(r.len()), r.start));
+
+        let overlaps = |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+        let mut mentions: Vec<(Range<usize>, Option<usize>)> = Vec::new();
+        for (range, ingredients) in candidates {
+            if taken.iter().any(|r| overlaps(r, &range))
+                || mentions.iter().any(|(r, _)| overlaps(r, &range))
+            {
+                continue;
+            }
+            let ingredient = ingredients.iter().copied().find(|i| !self.annotated[*i]);
+            if let Some(i) = ingredient {
+                self.annotated[i] = true;
+                self.found.push(i);
+            } else {
+                self.found.extend(&ingredients);
+            }
+            mentions.push((range, ingredient));
+        }
+        mentions.sort_by_key(|(r, _)| r.start);
+        mentions
+    }
+
+    /// The ingredients mentioned since the last call, in the order of the sections.
+    fn take_found(&mut self) -> Vec<usize> {
+        let mut found = std::mem::take(&mut self.found);
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+enum Mark {
+    Timer(u32),
+    Mention(Option<usize>),
+}
+
+create_formatter!(InstructionsFormatter<Mentions>, {
+    NodeValue::Text(ref literal) => |context, entering| {
+        if entering {
+            let mut mentions = std::mem::take(&mut context.user);
+            write_text(context, literal, &mut mentions)?;
+            context.user = mentions;
+        }
+    },
+});
+
+/// Escapes a text, wraps the durations in it in `<span class="timer" data-seconds="…">`, which
+/// the recipe page turns into timers, and adds the amounts of the ingredients mentioned.
+fn write_text(
+    output: &mut dyn std::fmt::Write,
+    text: &str,
+    mentions: &mut Mentions,
+) -> std::fmt::Result {
+    let mut marks: Vec<_> = find_durations(text)
+        .into_iter()
+        .map(|(range, seconds)| (range, Mark::Timer(seconds)))
+        .collect();
+    let timers: Vec<_> = marks.iter().map(|(range, _)| range.clone()).collect();
+    for (range, ingredient) in mentions.find(text, &timers) {
+        marks.push((range, Mark::Mention(ingredient)));
+    }
+    marks.sort_by_key(|(range, _)| range.start);
+
+    let mut offset = 0;
+    for (range, mark) in marks {
+        escape(output, &text[offset..range.start])?;
+        match mark {
+            Mark::Timer(seconds) => {
+                write!(output, r#"<span class="timer" data-seconds="{seconds}">"#)?;
+                escape(output, &text[range.clone()])?;
+                output.write_str("</span>")?;
+            }
+            Mark::Mention(ingredient) => {
+                escape(output, &text[range.clone()])?;
+                if let Some(amount) = ingredient.and_then(|i| mentions.amounts[i].as_deref()) {
+                    output.write_str(amount)?;
+                }
+            }
+        }
+        offset = range.end;
+    }
+    escape(output, &text[offset..])
+}
+
+/// Appends a block as Markdown, separated from the previous one by a blank line.
+fn push_markdown<'a>(markdown: &mut String, node: &'a AstNode<'a>, options: &Options<'_>) {
+    if !markdown.is_empty() {
+        markdown.push_str("\n\n");
+    }
+    format_commonmark(node, options, markdown).unwrap();
+    markdown.truncate(markdown.trim_end().len());
+}
+
+/// Renders the instructions as a whole and split into steps: the items of ordered lists and the
+/// other top-level blocks, like paragraphs. Headings are kept with the step that follows them.
+/// The amounts after mentions of ingredients are multiplied by `factor`.
+pub fn bake_instructions(
+    instructions: &str,
+    ingredients: &[&Ingredient],
+    factor: f64,
+    catalogue: &CatalogueData,
+) -> (String, Vec<Step>) {
+    let options = md_options();
+    let arena = Arena::new();
+    let root = parse_document(&arena, instructions, &options);
+
+    let mut html = String::new();
+    let mentions = Mentions::new(ingredients, factor, catalogue);
+    InstructionsFormatter::format_document(root, &options, &mut html, mentions).unwrap();
+
+    // Rendered again from the start, so the amounts are at the same mentions as above.
+    let mut mentions = Mentions::new(ingredients, factor, catalogue);
+    let mut steps = Vec::new();
+    let mut headings = String::new();
+    let mut headings_markdown = String::new();
+    for block in root.children() {
+        let parts: Vec<_> = match block.data().value {
+            NodeValue::Heading(_) => {
+                mentions = InstructionsFormatter::format_document(
+                    block,
+                    &options,
+                    &mut headings,
+                    mentions,
+                )
+                .unwrap();
+                push_markdown(&mut headings_markdown, block, &options);
+                continue;
+            }
+            NodeValue::List(list) if list.list_type == ListType::Ordered => {
+                block.children().collect()
+            }
+            _ => vec![block],
+        };
+        for part in parts {
+            let mut html = std::mem::take(&mut headings);
+            let mut markdown = std::mem::take(&mut headings_markdown);
+            // Only the content of list items, the step is not part of a list anymore.
+            let nodes: Vec<_> = if matches!(part.data().value, NodeValue::Item(_)) {
+                part.children().collect()
+            } else {
+                vec![part]
+            };
+            for node in nodes {
+                mentions =
+                    InstructionsFormatter::format_document(node, &options, &mut html, mentions)
+                        .unwrap();
+                push_markdown(&mut markdown, node, &options);
+            }
+            steps.push(Step {
+                html,
+                markdown,
+                ingredients: mentions.take_found(),
+            });
+        }
+    }
+    if !headings.is_empty() {
+        steps.push(Step {
+            html: headings,
+            markdown: headings_markdown,
+            ingredients: mentions.take_found(),
+        });
+    }
+    (html, steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Step, bake_instructions};
+    use crate::catalogue::{CatalogueData, CatalogueEntry};
+    use crate::recipe::Ingredient;
+
+    #[test]
+    fn test_bake_instructions() {
+        let (html, steps) = bake_instructions(
+            "1. **Teig** 1 Std. gehen lassen\n2. `20 min` <b>backen</b>",
+            &[],
+            1.0,
+            &CatalogueData::default(),
+        );
+        assert_eq!(
+            html,
+            "<ol>\n<li><strong>Teig</strong> \
+             <span class=\"timer\" data-seconds=\"3600\">1 Std.</span> gehen lassen</li>\n\
+             <li><code>20 min</code> <!-- raw HTML omitted -->backen<!-- raw HTML omitted --></li>\n\
+             </ol>\n"
+        );
+        assert_eq!(steps.len(), 2);
+    }
+
+    #[test]
+    fn test_steps() {
+        let catalogue = CatalogueData::new(BTreeMap::from([(
+            "Ei".into(),
+            CatalogueEntry {
+                plural: Some("Eier".into()),
+                ..CatalogueEntry::default()
+            },
+        )]));
+        let ingredient = |name: &str, amount: &str, unit: Option<&str>| Ingredient {
+            name: name.into(),
+            amount: amount.into(),
+            unit: unit.map(Into::into),
+            recipe: None,
+        };
+        let ingredients = [
+            ingredient("Mehl", "200", Some("g")),
+            ingredient("Ei", "2", None),
+            ingredient("Milch", "etwas", None),
+            ingredient("Mehl", "1", Some("EL")),
+        ];
+        let ingredients: Vec<_> = ingredients.iter().collect();
+        let amount = |amount: &str, unit: &str| {
+            format!(
+                r#" <span class="ingredient-mention">(<span class="mention-amount" data-amount="{amount}">{amount}</span>{unit})</span>"#
+            )
+        };
+
+        let (html, steps) = bake_instructions(
+            "## Teig\n\n1. Das **Mehl** und die Eier\n   verrühren.\n2. 10 min ruhen lassen, \
+             Mehlreste abwischen.\n\nDann die MILCH und das übrige mehl dazu.",
+            &ingredients,
+            2.0,
+            &catalogue,
+        );
+        let mehl = amount("400", "g");
+        let eier = amount("4", "");
+        let mehl_2 = amount("2", " EL");
+        assert_eq!(
+            steps,
+            [
+                Step {
+                    html: format!(
+                        "<h2>Teig</h2>\nDas <strong>Mehl{mehl}</strong> und die Eier{eier}\n\
+                         verrühren."
+                    ),
+                    markdown: "## Teig\n\nDas **Mehl** und die Eier\nverrühren.".into(),
+                    ingredients: vec![0, 1],
+                },
+                Step {
+                    html: "<span class=\"timer\" data-seconds=\"600\">10 min</span> ruhen lassen, \
+                           Mehlreste abwischen."
+                        .into(),
+                    markdown: "10 min ruhen lassen, Mehlreste abwischen.".into(),
+                    ingredients: vec![],
+                },
+                Step {
+                    html: format!(
+                        "<p>Dann die MILCH <span class=\"ingredient-mention\">(etwas)</span> und \
+                         das übrige mehl{mehl_2} dazu.</p>\n"
+                    ),
+                    markdown: "Dann die MILCH und das übrige mehl dazu.".into(),
+                    ingredients: vec![2, 3],
+                },
+            ]
+        );
+        assert!(html.starts_with(&format!("<h2>Teig</h2>\n<ol>\n<li>Das <strong>Mehl{mehl}")));
+        assert!(html.ends_with(&format!("das übrige mehl{mehl_2} dazu.</p>\n")));
+        assert!(
+            bake_instructions("", &ingredients, 1.0, &catalogue)
+                .1
+                .is_empty()
+        );
+
+        // Blank names, e.g. in old recipes, are not mentioned anywhere.
+        let blank = [ingredient(" ", "1", None), ingredient("Ei", "1", None)];
+        let blank: Vec<_> = blank.iter().collect();
+        let (html, steps) = bake_instructions("1. Rühren.\n2. Ei dazu.", &blank, 1.0, &catalogue);
+        let mentioned: Vec<_> = steps.iter().map(|s| s.ingredients.clone()).collect();
+        assert_eq!(mentioned, [vec![], vec![1]]);
+        let ei = amount("1", "");
+        assert_eq!(
+            html,
+            format!("<ol>\n<li>Rühren.</li>\n<li>Ei{ei} dazu.</li>\n</ol>\n")
+        );
+        assert_eq!(steps[0].html, "Rühren.");
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::fs::read_to_string;
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+
+use crate::error::Error;
+use crate::recipes::{handle_io_error, read_error};
+
+/// Data kept in memory and written as a whole to one JSON file on every change, like the
+/// catalogue or the pantries.
+pub struct JsonStore<T> {
+    data: RwLock<T>,
+    path: Mutex<PathBuf>,
+}
+
+impl<T: Default + DeserializeOwned + Serialize> JsonStore<T> {
+    /// Starts with the default if the file does not exist yet.
+    pub async fn load(path: PathBuf) -> std::io::Result<JsonStore<T>> {
+        Ok(JsonStore {
+            data: RwLock::new(read(&path).await?),
+            path: Mutex::new(path),
+        })
+    }
+
+    /// Reads the file again, after it was changed by someone else. Keeps the data if the file
+    /// cannot be read.
+    pub async fn reload(&self) -> std::io::Result<()> {
+        let path = self.path.lock().await;
+        let data = read(&path).await?;
+        *self.data.write().await = data;
+        Ok(())
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.data.read().await
+    }
+
+    /// Changes the data and writes the file. Changes are written in the order they are made, and
+    /// an error of `change` leaves the file alone.
+    pub async fn update<R>(
+        &self,
+        change: impl FnOnce(&mut T) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let path = self.path.lock().await;
+        let (content, result) = {
+            let mut data = self.data.write().await;
+            let result = change(&mut data)?;
+            (serde_json::to_string(&*data).unwrap(), result)
+        };
+        tokio::fs::write(&*path, content)
+            .await
+            .map_err(|e| handle_io_error(&path, &e))?;
+        Ok(result)
+    }
+}
+
+async fn read<T: Default + DeserializeOwned>(path: &Path) -> std::io::Result<T> {
+    match read_to_string(path).await {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| read_error(path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(read_error(path, e)),
+    }
+}
@@ -0,0 +1,149 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::metadata::LevelFilter;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, fmt};
+
+use crate::settings::{LogConfig, LogFormat};
+
+/// Flushes the exported traces when dropped.
+pub struct Telemetry(Option<SdkTracerProvider>);
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = &self.0
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("Failed to export traces: {e}");
+        }
+    }
+}
+
+/// Installs the global subscriber. Must be called outside of an async runtime when exporting
+/// traces.
+pub fn setup(config: &LogConfig) -> std::io::Result<Telemetry> {
+    let provider = config
+        .otlp_endpoint
+        .as_deref()
+        .map(tracer_provider)
+        .transpose()?;
+    if provider.is_some() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    }
+
+    let fmt = fmt::layer().with_span_events(FmtSpan::NEW | FmtSpan::CLOSE);
+    let fmt = match config.format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt.json().boxed(),
+    };
+    let otlp = provider
+        .as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer("recipes")));
+
+    tracing_subscriber::registry()
+        .with(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .with(fmt)
+        .with(otlp)
+        .try_init()
+        .map_err(|e| std::io::Error::other(format!("Failed to set up logging: {e}")))?;
+
+    Ok(Telemetry(provider))
+}
+
+/// Exports spans with OTLP over HTTP, e.g. to `http://localhost:4318/v1/traces`.
+fn tracer_provider(endpoint: &str) -> std::io::Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| std::io::Error::other(format!("Failed to set up trace export: {e}")))?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("recipes").build())
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::web::{Bytes, Data, post};
+    use actix_web::{App, HttpResponse, HttpServer};
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::tracer_provider;
+    use crate::routes::tests::app;
+
+    /// Accepts OTLP requests and keeps their bodies.
+    fn start_collector() -> (String, Arc<Mutex<Vec<u8>>>) {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let data = Data::from(bodies.clone());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let server = HttpServer::new(move || {
+                    App::new().app_data(data.clone()).route(
+                        "/v1/traces",
+                        post().to(|body: Bytes, received: Data<Mutex<Vec<u8>>>| async move {
+                            received.lock().unwrap().extend_from_slice(&body);
+                            HttpResponse::Ok().finish()
+                        }),
+                    )
+                })
+                .workers(1)
+                .bind("127.0.0.1:0")
+                .unwrap();
+                sender.send(server.addrs()[0]).unwrap();
+                server.run().await.unwrap();
+            });
+        });
+        let address = receiver.recv().unwrap();
+        (format!("http://{address}/v1/traces"), bodies)
+    }
+
+    #[test]
+    fn test_otlp_export() {
+        let (endpoint, received) = start_collector();
+        let provider = tracer_provider(&endpoint).unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        // The exporter sends from its own thread, so only the app runs in an async runtime.
+        let guard = tracing::subscriber::set_default(subscriber);
+        actix_web::rt::System::new().block_on(async {
+            let app = actix_web::test::init_service(app().await).await;
+            let req = actix_web::test::TestRequest::with_uri("/recipe/test-1").to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+        });
+        drop(guard);
+        provider.shutdown().unwrap();
+
+        let received = received.lock().unwrap();
+        for expected in [
+            "GET /recipe/{recipe}",
+            "http.route",
+            "request_id",
+            "recipe_id",
+            "test-1",
+        ] {
+            assert!(
+                received
+                    .windows(expected.len())
+                    .any(|w| w == expected.as_bytes()),
+                "{expected} was not exported"
+            );
+        }
+    }
+}
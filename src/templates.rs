@@ -1,24 +1,33 @@
+use std::error::Error as _;
 use std::path::PathBuf;
 
 use tera::{Context, Kwargs, State, Tera};
 use tokio::task::spawn_blocking;
+use tracing::error;
 
+use crate::duration::format_duration;
+use crate::error::Error;
+use crate::i18n::{Language, messages, translate};
+use crate::recipe::bake_string;
+use crate::recipes::read_error;
 use crate::unit::unit_needs_space;
 
 pub struct Templates(Tera);
 
 impl Templates {
-    pub async fn load_directory(dir: PathBuf) -> Self {
+    pub async fn load_directory(dir: PathBuf) -> std::io::Result<Self> {
         spawn_blocking(move || {
             let files = std::fs::read_dir(&dir)
-                .unwrap_or_else(|e| panic!("Failed to read dir {}: {e}", dir.display()))
-                .map(|entry| {
-                    let path = entry
-                        .unwrap_or_else(|e| panic!("Failed to read dir {}: {e}", dir.display()))
-                        .path();
-                    let name = path.file_name().unwrap().to_str().unwrap().to_owned();
-                    (path, Some(name))
-                });
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| {
+                            let path = entry?.path();
+                            let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+                            Ok((path, Some(name)))
+                        })
+                        .collect::<std::io::Result<Vec<_>>>()
+                })
+                .map_err(|e| read_error(&dir, e))?;
 
             let mut tera = Tera::new();
             tera.register_test(
@@ -35,18 +44,101 @@ impl Templates {
                     }
                 },
             );
+            tera.register_filter("t", |key: &str, kwargs: Kwargs, state: &State<'_>| {
+                let mut message = translate(language_of(state), key).to_owned();
+                // Messages are trusted, but the values inserted into them are escaped, as
+                // autoescaping is off.
+                for (name, value) in kwargs.iter() {
+                    let value = bake_string(&value.to_string());
+                    message = message.replace(&format!("{{{name}}}"), &value);
+                }
+                message
+            });
+            tera.register_filter("duration", |minutes: u32, _: Kwargs, _: &State<'_>| {
+                format_duration(minutes)
+            });
+            tera.register_function("error_messages", |_: Kwargs, state: &State<'_>| {
+                serde_json::to_string(&messages(language_of(state), "error.")).unwrap()
+            });
             tera.add_template_files(files)
-                .unwrap_or_else(|e| panic!("Failed to parse template: {e}"));
+                .map_err(|e| read_error(&dir, describe(&e)))?;
             tera.autoescape_on(std::iter::empty::<&str>());
-            Templates(tera)
+            Ok(Templates(tera))
         })
         .await
         .unwrap()
     }
 
-    pub fn render(&self, name: &str, context: &Context) -> String {
-        self.0
-            .render(name, context)
-            .unwrap_or_else(|e| panic!("Failed to render template {name}: {e}"))
+    pub fn render(
+        &self,
+        name: &str,
+        language: Language,
+        context: &Context,
+    ) -> Result<String, Error> {
+        let mut context = context.clone();
+        context.insert("lang", language.code());
+        self.0.render(name, &context).map_err(|e| {
+            error!("Failed to render template {name}: {}", describe(&e));
+            Error::Internal
+        })
+    }
+}
+
+/// The `lang` variable is set by [`Templates::render`]. Components have to get it passed.
+fn language_of(state: &State<'_>) -> Language {
+    state
+        .get::<String>("lang")
+        .ok()
+        .flatten()
+        .and_then(|l| Language::from_code(&l))
+        .unwrap_or_default()
+}
+
+/// Tera puts the details of errors in templates into the sources.
+fn describe(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message = format!("{message}: {e}");
+        source = e.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use tera::Context;
+
+    use super::Templates;
+    use crate::i18n::Language;
+
+    #[tokio::test]
+    async fn test_syntax_error() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("broken.html"), "{% if %}").unwrap();
+        let Err(e) = Templates::load_directory(dir.path().to_owned()).await else {
+            panic!("expected a syntax error");
+        };
+        assert!(e.to_string().contains("broken.html"), "{e}");
+    }
+
+    #[tokio::test]
+    async fn test_translate_escapes_values() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("title.html"),
+            "{{ 'edit.title' | t(name=name) }}",
+        )
+        .unwrap();
+        let templates = Templates::load_directory(dir.path().to_owned())
+            .await
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("name", "<b>Kuchen</b>");
+        let rendered = templates
+            .render("title.html", Language::default(), &context)
+            .unwrap();
+        assert_eq!(rendered, "Bearbeiten - &lt;b&gt;Kuchen&lt;/b&gt;");
     }
 }
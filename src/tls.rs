@@ -0,0 +1,107 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use actix_web::HttpResponse;
+use actix_web::http::header::LOCATION;
+use rustls::ServerConfig;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+use crate::recipes::read_error;
+use crate::settings::TlsConfig;
+
+/// Resolves to the configured certificate, which can be reloaded while the server is running.
+#[derive(Debug)]
+pub struct Certificates {
+    config: TlsConfig,
+    provider: Arc<CryptoProvider>,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl Certificates {
+    pub fn load(config: TlsConfig) -> std::io::Result<Arc<Certificates>> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let key = RwLock::new(Arc::new(read(&config, &provider)?));
+        Ok(Arc::new(Certificates {
+            config,
+            provider,
+            key,
+        }))
+    }
+
+    /// Replaces the certificate if the files are valid, otherwise keeps the current one.
+    pub fn reload(&self) -> std::io::Result<()> {
+        let key = read(&self.config, &self.provider)?;
+        *self.key.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+
+    pub fn paths(&self) -> [&Path; 2] {
+        [&self.config.certificate, &self.config.key]
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> std::io::Result<ServerConfig> {
+        Ok(ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| std::io::Error::other(format!("Invalid TLS configuration: {e}")))?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone()))
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+fn read(config: &TlsConfig, provider: &CryptoProvider) -> std::io::Result<CertifiedKey> {
+    let certificates = CertificateDer::pem_file_iter(&config.certificate)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| read_error(&config.certificate, e))?;
+    let key = PrivateKeyDer::from_pem_file(&config.key).map_err(|e| read_error(&config.key, e))?;
+    CertifiedKey::from_der(certificates, key, provider)
+        .map_err(|e| std::io::Error::other(format!("Invalid TLS configuration: {e}")))
+}
+
+/// Extracts the port from a listen address like `0.0.0.0:8443`.
+pub fn port_of(address: &str) -> Option<u16> {
+    address.rsplit_once(':')?.1.parse().ok()
+}
+
+/// Redirects a plain HTTP request to the same path on the HTTPS listener.
+pub fn redirect(host: &str, https_port: Option<u16>, path: &str) -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .insert_header((LOCATION, redirect_url(host, https_port, path)))
+        .finish()
+}
+
+fn redirect_url(host: &str, https_port: Option<u16>, path: &str) -> String {
+    let host = match host.find(']') {
+        Some(end) => &host[..=end],
+        None => host.split(':').next().unwrap_or(host),
+    };
+    match https_port {
+        Some(443) | None => format!("https://{host}{path}"),
+        Some(port) => format!("https://{host}:{port}{path}"),
+    }
+}
+
+#[test]
+fn test_redirect_url() {
+    assert_eq!(
+        redirect_url("example.org", Some(443), "/recipe/a?b=c"),
+        "https://example.org/recipe/a?b=c"
+    );
+    assert_eq!(
+        redirect_url("example.org:8080", port_of("0.0.0.0:8443"), "/"),
+        "https://example.org:8443/"
+    );
+    assert_eq!(
+        redirect_url("[::1]:8080", port_of("[::]:8443"), "/"),
+        "https://[::1]:8443/"
+    );
+}
@@ -0,0 +1,27 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+
+pub fn generate() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn test() {
+    let a = generate();
+    assert_eq!(a.len(), 43);
+    assert_ne!(a, generate());
+    assert_eq!(hash(&a), hash(&a));
+    assert_ne!(hash(&a), hash(&generate()));
+}
@@ -21,6 +21,59 @@ pub(crate) fn unit_needs_space(s: &str) -> bool {
     !SI_UNITS.contains(&ch)
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Mass,
+    Volume,
+}
+
+/// The dimension of a unit and its size in grams or millilitres.
+fn unit_size(unit: &str) -> Option<(Dimension, f64)> {
+    let size = match unit.trim().to_lowercase().as_str() {
+        "µg" | "μg" => (Dimension::Mass, 0.000_001),
+        "mg" => (Dimension::Mass, 0.001),
+        "g" => (Dimension::Mass, 1.0),
+        "kg" => (Dimension::Mass, 1000.0),
+        "ml" => (Dimension::Volume, 1.0),
+        "tl" => (Dimension::Volume, 5.0),
+        "cl" => (Dimension::Volume, 10.0),
+        "el" => (Dimension::Volume, 15.0),
+        "dl" => (Dimension::Volume, 100.0),
+        "l" => (Dimension::Volume, 1000.0),
+        _ => return None,
+    };
+    Some(size)
+}
+
+/// How many grams one of the unit is. Volume units need the density in g/ml.
+pub(crate) fn grams_per_unit(unit: &str, density: Option<f64>) -> Option<f64> {
+    match unit_size(unit)? {
+        (Dimension::Mass, size) => Some(size),
+        (Dimension::Volume, size) => density.map(|d| size * d),
+    }
+}
+
+/// Converts an amount between mass and volume units. Converting between a mass and a volume
+/// needs the density in g/ml.
+pub(crate) fn convert(amount: f64, from: &str, to: &str, density: Option<f64>) -> Option<f64> {
+    let (from_dimension, from_size) = unit_size(from)?;
+    let (to_dimension, to_size) = unit_size(to)?;
+    let factor = match (from_dimension, to_dimension) {
+        (Dimension::Mass, Dimension::Mass) | (Dimension::Volume, Dimension::Volume) => 1.0,
+        (Dimension::Volume, Dimension::Mass) => density?,
+        (Dimension::Mass, Dimension::Volume) => 1.0 / density?,
+    };
+    Some(amount * from_size * factor / to_size)
+}
+
+/// Whether an amount with the unit counts pieces, like "2 Eier" or "1 Stück Ingwer".
+pub(crate) fn is_piece_unit(unit: Option<&str>) -> bool {
+    match unit.map(str::trim) {
+        None | Some("") => true,
+        Some(unit) => ["stück", "stk", "stk."].contains(&unit.to_lowercase().as_str()),
+    }
+}
+
 #[test]
 fn test() {
     assert!(unit_needs_space("a"));
@@ -29,4 +82,14 @@ fn test() {
     assert!(!unit_needs_space("g"));
     assert!(unit_needs_space("gully"));
     assert!(unit_needs_space("kk"));
+    assert_eq!(grams_per_unit("kg", None), Some(1000.0));
+    assert_eq!(grams_per_unit("EL", None), None);
+    assert_eq!(grams_per_unit("EL", Some(0.5)), Some(7.5));
+    assert_eq!(convert(200.0, "ml", "g", Some(0.5)), Some(100.0));
+    assert_eq!(convert(150.0, "g", "l", Some(0.5)), Some(0.3));
+    assert_eq!(convert(0.5, "kg", "g", None), Some(500.0));
+    assert_eq!(convert(200.0, "ml", "g", None), None);
+    assert_eq!(convert(2.0, "Stück", "g", Some(1.0)), None);
+    assert!(is_piece_unit(Some("Stück")));
+    assert!(!is_piece_unit(Some("g")));
 }
@@ -1,10 +1,12 @@
-const errors = {
-    "already-exists": "Ein Rezept mit diesem Namen existiert bereits.",
-    "unauthorized": "Zugriff verweigert.",
-    "empty-id": "Ein Rezept muss einen Namen haben.",
-    "internal-error": "Internal server error.",
-    "user-name-too-short": "Username ist zu kurz, mindestens 4 Zeichen.",
-    "password-too-short": "Passwort ist zu kurz, mindestens 8 Zeichen.",
+let errors = null
+
+/** Looks up a translated error message embedded by the page. */
+function errorMessage(code) {
+    if (errors === null) {
+        const element = document.getElementById("error-messages")
+        errors = element ? JSON.parse(element.textContent) : {}
+    }
+    return errors[code]
 }
 
 function extendSection(section) {
@@ -13,6 +15,7 @@ function extendSection(section) {
         return this.ingredientsTable.childNodes
     }
     section.headingInput = section.querySelector(".ingredients-section-name-input")
+    section.recipeInput = section.querySelector(".ingredients-section-recipe-input")
 }
 
 function doRedirect(xhr) {
@@ -58,8 +61,27 @@ function createInitialState() {
         title: document.getElementById("name"),
         source: document.getElementById("source"),
         categories: document.getElementById("categories"),
+        servings: document.getElementById("servings"),
+        prepTime: document.getElementById("prep-time"),
+        cookTime: document.getElementById("cook-time"),
+        restTime: document.getElementById("rest-time"),
+        nutritionFoods: document.getElementById("nutrition-foods"),
         findSections: function () {
             return this.ingredientsSections.querySelectorAll(":scope > div")
         }
     }
 }
+
+// Keeps the screen from turning off while cooking, also after switching back to the tab.
+function keepScreenOn() {
+    let screenLock = null;
+    navigator.wakeLock.request('screen').then(lock => {
+        screenLock = lock;
+    });
+
+    document.addEventListener('visibilitychange', async () => {
+        if (screenLock !== null && document.visibilityState === 'visible') {
+            screenLock = await navigator.wakeLock.request('screen');
+        }
+    });
+}
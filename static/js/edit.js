@@ -13,11 +13,39 @@ function initToolbar(node, toolbar) {
     })
 }
 
+// Minutes of inputs like "90", "1:30", "1 h 30 min" or "1,5 Stunden". Null if empty, NaN if
+// not understood.
+function parseDuration(text) {
+    text = text.trim().toLowerCase()
+    if (text === "")
+        return null
+    const clock = /^(\d+):(\d{1,2})$/.exec(text)
+    if (clock)
+        return parseInt(clock[1]) * 60 + parseInt(clock[2])
+    const part = /(\d+(?:[.,]\d+)?)\s*(tage?n?|days?|d|stunden?|std\.?|hours?|h|minut(?:en|es|e)|min\.?|m)?\s*/gy
+    let minutes = 0
+    let match
+    while (part.lastIndex < text.length && (match = part.exec(text)) !== null) {
+        const number = parseFloat(match[1].replace(",", "."))
+        const unit = match[2] || "min"
+        if (unit.startsWith("t") || unit.startsWith("d"))
+            minutes += number * 24 * 60
+        else if (unit.startsWith("s") || unit.startsWith("h"))
+            minutes += number * 60
+        else
+            minutes += number
+    }
+    if (part.lastIndex !== text.length)
+        return NaN
+    return Math.round(minutes)
+}
+
 function extendRow(row) {
     row.ingredient = {
         nameInput: row.querySelector(".ingredient-name-input"),
         unitInput: row.querySelector(".ingredient-unit-input"),
         amountInput: row.querySelector(".ingredient-amount-input"),
+        recipeInput: row.querySelector(".ingredient-recipe-input"),
     }
 }
 
@@ -64,6 +92,7 @@ function createRequestForButton(b, async) {
     console.assert(method)
     console.assert(action)
     xhr.open(method, action, async)
+    xhr.setRequestHeader("X-CSRF-Token", document.querySelector("meta[name=csrf-token]").content)
     return xhr
 }
 
@@ -190,13 +219,85 @@ document.addEventListener("DOMContentLoaded", function () {
         }
     }
 
+    function inputForPath(path) {
+        const parts = path.split(".")
+        switch (parts[0]) {
+            case "Name":
+                return info.title
+            case "Description":
+                return info.description
+            case "Instructions":
+                return info.instructions
+            case "Source":
+                return info.source
+            case "Categories":
+                return info.categories
+            case "Servings":
+                return info.servings
+            case "PrepTime":
+                return info.prepTime
+            case "CookTime":
+                return info.cookTime
+            case "RestTime":
+                return info.restTime
+            case "NutritionFoods":
+                return info.nutritionFoods
+            case "IngredientsSections": {
+                const section = info.findSections()[parts[1]]
+                if (!section)
+                    return null
+                if (parts[2] === "Heading")
+                    return section.headingInput
+                if (parts[2] === "Recipe")
+                    return section.recipeInput
+                const row = section.ingredients()[parts[3]]
+                if (!row)
+                    return null
+                return {
+                    Name: row.ingredient.nameInput,
+                    Amount: row.ingredient.amountInput,
+                    Unit: row.ingredient.unitInput,
+                    Recipe: row.ingredient.recipeInput,
+                }[parts[4]]
+            }
+        }
+        return null
+    }
+
+    function clearFieldErrors() {
+        document.querySelectorAll(".is-invalid").forEach(function (input) {
+            input.classList.remove("is-invalid")
+            input.removeAttribute("title")
+        })
+    }
+
+    function showFieldErrors(fields) {
+        const messages = new Set()
+        fields.forEach(function (field) {
+            const message = errorMessage(field.code) || field.code
+            messages.add(message)
+            const input = inputForPath(field.path)
+            if (input) {
+                input.classList.add("is-invalid")
+                input.title = message
+            }
+        })
+        return Array.from(messages).join(" ")
+    }
+
     function serverError(xhr) {
         const text = xhr.responseText.trim();
+        let response = null;
+        if (xhr.getResponseHeader("Content-Type") === "application/json") {
+            response = JSON.parse(text)
+        }
         let message;
-        if (errors.hasOwnProperty(text)) {
-            message = errors[text];
+        if (response && response.fields) {
+            message = showFieldErrors(response.fields);
+        } else if (errorMessage(text)) {
+            message = errorMessage(text);
         } else {
-            message = "Antwort des Servers: " + text;
+            message = errorMessage("server-response").replace("{response}", text);
         }
         errorToastContent.innerText = message;
         errorToast.show()
@@ -210,6 +311,27 @@ document.addEventListener("DOMContentLoaded", function () {
         const instructions = info.instructions.value
         const source = info.source.value
         const categories = info.categories.value.split(",")
+        const servings = info.servings.value ? parseInt(info.servings.value) : null
+        const times = {
+            PrepTime: parseDuration(info.prepTime.value),
+            CookTime: parseDuration(info.cookTime.value),
+            RestTime: parseDuration(info.restTime.value),
+        }
+        const invalidTimes = Object.keys(times)
+            .filter(path => isNaN(times[path]))
+            .map(path => ({path, code: "invalid-duration"}))
+        if (invalidTimes.length > 0) {
+            errorToastContent.innerText = showFieldErrors(invalidTimes)
+            errorToast.show()
+            setButtonsDisabled(false)
+            return
+        }
+        const nutritionFoods = {}
+        info.nutritionFoods.value.split("\n").forEach(function (line) {
+            const separator = line.indexOf("=")
+            if (separator !== -1)
+                nutritionFoods[line.slice(0, separator).trim()] = line.slice(separator + 1).trim()
+        })
         const sections = info.findSections()
         const resArray = new Array(sections.length)
         const imageElement = document.getElementById("image")
@@ -227,10 +349,12 @@ document.addEventListener("DOMContentLoaded", function () {
                     Amount: row.ingredient.amountInput.value,
                     Unit: row.ingredient.unitInput.value,
                     Name: row.ingredient.nameInput.value,
+                    Recipe: row.ingredient.recipeInput.value,
                 }
             }
             resArray[i] = {
                 Heading: heading,
+                Recipe: section.recipeInput.value,
                 Ingredients: ingredientsArray,
             }
         }
@@ -243,6 +367,9 @@ document.addEventListener("DOMContentLoaded", function () {
             Instructions: instructions,
             Source: source,
             Categories: categories,
+            Servings: servings,
+            ...times,
+            NutritionFoods: nutritionFoods,
         }
         const json = JSON.stringify(res)
         const xhr = createRequestForButton(submitButton, true)
@@ -253,6 +380,7 @@ document.addEventListener("DOMContentLoaded", function () {
 
     submitButton.addEventListener("click", function () {
         setButtonsDisabled(true)
+        clearFieldErrors()
         saveRecipe()
     })
 
@@ -37,13 +37,25 @@ document.addEventListener("DOMContentLoaded", function () {
 
     scanIngredients(info, validRows)
 
+    const cookedFactorInput = document.getElementById("cooked-factor")
+    const stepsLink = document.getElementById("steps-link")
+    const mentionAmounts = document.querySelectorAll("#instructions .mention-amount[data-amount]")
+
     function scaleAllByFactor(factor) {
         validRows.forEach(function (row) {
             row.ingredient.scaleAmountByFactor(factor)
         })
+        if (cookedFactorInput)
+            cookedFactorInput.value = factor
+        mentionAmounts.forEach(function (span) {
+            span.innerText = Math.round((span.dataset.amount * factor + Number.EPSILON) * 1000) / 1000
+        })
+        if (stepsLink)
+            stepsLink.search = factor === 1 ? "" : "?factor=" + factor
     }
 
-    const localStorageKey = location.pathname;
+    // Keyed by the permanent id, so the state survives renames.
+    const localStorageKey = "/recipe/" + document.querySelector("[data-recipe-id]").dataset.recipeId;
     const scaleIngredientAmountInput = document.getElementById("scale-ingredient-amount")
     const scaleIngredientSelect = document.getElementById("scale-ingredient-select")
 
@@ -87,14 +99,71 @@ document.addEventListener("DOMContentLoaded", function () {
         update();
     }
 
-    let screenLock = null;
-    navigator.wakeLock.request('screen').then(lock => {
-        screenLock = lock;
-    });
+    keepScreenOn();
+});
+
+// Turns the durations marked in the instructions into timers, any number of which can run at
+// the same time. Notifies when one runs out.
+document.addEventListener("DOMContentLoaded", function () {
+    const instructions = document.getElementById("instructions")
+    if (!instructions)
+        return
+    const {timerStart, timerDone} = instructions.dataset
+
+    function format(seconds) {
+        const hours = Math.floor(seconds / 3600)
+        const minutes = String(Math.floor(seconds / 60) % 60).padStart(hours > 0 ? 2 : 1, "0")
+        const rest = String(seconds % 60).padStart(2, "0")
+        return (hours > 0 ? hours + ":" : "") + minutes + ":" + rest
+    }
+
+    function notify(text) {
+        if ("Notification" in window && Notification.permission === "granted")
+            new Notification(document.title, {body: text})
+        if (navigator.vibrate)
+            navigator.vibrate([300, 100, 300])
+    }
+
+    instructions.querySelectorAll(".timer[data-seconds]").forEach(function (span) {
+        const seconds = parseInt(span.dataset.seconds)
+        const button = document.createElement("button")
+        button.type = "button"
+        button.className = "btn btn-sm btn-outline-secondary timer-button fa fa-clock-o"
+        button.title = timerStart
+        span.after(button)
 
-    document.addEventListener('visibilitychange', async () => {
-        if (screenLock !== null && document.visibilityState === 'visible') {
-            screenLock = await navigator.wakeLock.request('screen');
+        let end = null
+        let interval = null
+
+        function stop() {
+            clearInterval(interval)
+            interval = null
+            button.innerText = ""
+            button.classList.remove("active")
         }
-    });
-});
+
+        function tick() {
+            const left = Math.max(0, Math.round((end - Date.now()) / 1000))
+            button.innerText = " " + format(left)
+            if (left === 0) {
+                stop()
+                button.classList.add("btn-success")
+                notify(timerDone.replace("{time}", span.innerText))
+            }
+        }
+
+        button.addEventListener("click", function () {
+            if (interval !== null) {
+                stop()
+                return
+            }
+            if ("Notification" in window && Notification.permission === "default")
+                Notification.requestPermission()
+            button.classList.remove("btn-success")
+            button.classList.add("active")
+            end = Date.now() + seconds * 1000
+            interval = setInterval(tick, 1000)
+            tick()
+        })
+    })
+})
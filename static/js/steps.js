@@ -0,0 +1,41 @@
+// Shows one step of the instructions at a time and highlights the ingredients it uses. The
+// number of the step is kept in the URL, so reloading does not start over.
+document.addEventListener("DOMContentLoaded", function () {
+    const steps = document.querySelectorAll("#instructions > .step")
+    if (steps.length === 0)
+        return
+    const ingredients = document.querySelectorAll("#step-ingredients [data-ingredient]")
+    const previous = document.getElementById("previous-step")
+    const next = document.getElementById("next-step")
+    let current = 0
+
+    function show(index) {
+        current = Math.max(0, Math.min(steps.length - 1, index))
+        steps.forEach(function (step, i) {
+            step.hidden = i !== current
+        })
+        const used = steps[current].dataset.ingredients.split(" ")
+        ingredients.forEach(function (ingredient) {
+            ingredient.classList.toggle("step-ingredient", used.includes(ingredient.dataset.ingredient))
+        })
+        previous.disabled = current === 0
+        next.disabled = current === steps.length - 1
+        history.replaceState(null, "", "#" + (current + 1))
+    }
+
+    previous.addEventListener("click", function () {
+        show(current - 1)
+    })
+    next.addEventListener("click", function () {
+        show(current + 1)
+    })
+    document.addEventListener("keydown", function (e) {
+        if (e.key === "ArrowLeft")
+            show(current - 1)
+        else if (e.key === "ArrowRight")
+            show(current + 1)
+    })
+
+    show((parseInt(location.hash.slice(1)) || 1) - 1)
+    keepScreenOn()
+})